@@ -1,36 +1,81 @@
 #version 450
 
-layout(location = 0) in vec2 v_tex_coord_raw;
-layout(location = 1) flat in ivec3 b_pos;
-layout(location = 2) flat in uint face;
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) flat in uint v_layer;
+layout(location = 2) in float v_light;
+layout(location = 3) in vec3 v_normal;
+layout(location = 4) in vec3 v_world_pos;
 
 layout(location = 0) out vec4 o_target;
 
-layout(set = 0, binding = 1) uniform texture2D t_color;
+layout(set = 0, binding = 1) uniform texture2DArray t_color;
 layout(set = 0, binding = 2) uniform sampler s_color;
 
-const float _2_PI = 6.283185307179586;
-const float PI = 3.1415926535897932384626433832795;
-const float PI_2 = 1.57079632679489661923;
-const float PI_4 = 0.785398163397448309616;
+// See `lighting::LightingUniforms`. The fourth component of each member is unused, except for the
+// fog density.
+layout(set = 0, binding = 4) uniform Lighting {
+    vec4 l_sun_direction;
+    vec4 l_sun_color;
+    vec4 l_ambient;
+    vec4 l_zenith_color;
+    vec4 l_fog;
+};
 
-void main() {
-    vec2 v_tex_coord = v_tex_coord_raw / 2.0;
-
-    if (b_pos.z >= 15) {
-        vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord);
-        o_target = tex;
-    } else if (b_pos.z >= 0) {
-        // If we are on the top face, use the grass texture.
-        if (face == 32) {
-            vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.5));
-            o_target = tex;
-        } else {
-            vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.0, 0.5));
-            o_target = tex;
+layout(set = 0, binding = 5) uniform texture2D t_shadow;
+layout(set = 0, binding = 6) uniform samplerShadow s_shadow;
+layout(set = 0, binding = 7) uniform Shadow {
+    mat4 s_transform;
+};
+
+// See `utils::ViewUniforms`.
+layout(set = 0, binding = 8) uniform View {
+    mat4 v_inverse_transform;
+    vec4 v_camera_pos;
+};
+
+// How far a fragment is pushed out along its normal before it is looked up in the shadow map, so
+// that surfaces don't shadow themselves.
+const float SHADOW_NORMAL_OFFSET = 0.05;
+
+// Returns how much of the sun reaches this fragment, from 0 (fully shadowed) to 1 (fully lit).
+float sun_visibility(vec3 normal) {
+    vec4 shadow_pos = s_transform * vec4(v_world_pos + normal * SHADOW_NORMAL_OFFSET, 1.0);
+    vec3 ndc = shadow_pos.xyz / shadow_pos.w;
+    // Clip space points up, and texture space points down.
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    // Anything outside of the shadow map is treated as lit.
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    // Percentage-closer filtering: average a 3x3 grid of comparisons, each of which the sampler
+    // already blends between the four nearest texels.
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(t_shadow, s_shadow), 0));
+    float visibility = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * texel;
+            visibility += texture(sampler2DShadow(t_shadow, s_shadow), vec3(uv + offset, ndc.z));
         }
-    } else {
-        vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.5, 0.0));
-        o_target = tex;
     }
+    return visibility / 9.0;
+}
+
+void main() {
+    // Texture coordinates count blocks across the face, and the sampler repeats, so each layer is
+    // tiled once per block across faces which cover several blocks.
+    vec4 color = texture(sampler2DArray(t_color, s_color), vec3(v_tex_coord, float(v_layer)));
+
+    // Lambertian diffuse lighting from the sun, wherever the sun isn't shadowed, on top of a flat
+    // ambient term.
+    vec3 normal = normalize(v_normal);
+    float diffuse = max(dot(normal, l_sun_direction.xyz), 0.0) * sun_visibility(normal);
+    vec3 light = l_ambient.rgb + l_sun_color.rgb * diffuse;
+
+    // Fade into the fog with distance. The fog is the color of the sky at the horizon, so the
+    // edge of the world disappears into it.
+    float fog_distance = l_fog.w * distance(v_world_pos, v_camera_pos.xyz);
+    float fog = 1.0 - exp(-fog_distance * fog_distance);
+
+    o_target = vec4(mix(color.rgb * light * v_light, l_fog.rgb, fog), color.a);
 }
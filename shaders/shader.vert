@@ -6,8 +6,10 @@ layout(location = 2) in vec2 v_tex_coord;
 layout(location = 3) in uint data;
 
 layout(location = 0) out vec2 o_v_tex_coord;
-layout(location = 1) out ivec3 o_b_pos;
-layout(location = 2) out uint face;
+layout(location = 1) flat out uint o_layer;
+layout(location = 2) out float o_light;
+layout(location = 3) out vec3 o_normal;
+layout(location = 4) out vec3 o_world_pos;
 
 layout(set = 0, binding = 0) uniform Camera {
     mat4 c_transform;
@@ -17,12 +19,30 @@ layout(set = 0, binding = 3) uniform Transform {
     mat4 u_transform;
 };
 
+// The face is a single `CuboidFaces` bit, so it can be turned straight into the face's normal.
+vec3 face_normal(uint face) {
+    switch (face) {
+        case 32u: return vec3(0.0, 0.0, 1.0);   // TOP
+        case 16u: return vec3(0.0, 0.0, -1.0);  // BOTTOM
+        case 8u: return vec3(0.0, 1.0, 0.0);    // FRONT
+        case 4u: return vec3(0.0, -1.0, 0.0);   // BACK
+        case 2u: return vec3(-1.0, 0.0, 0.0);   // LEFT
+        default: return vec3(1.0, 0.0, 0.0);    // RIGHT
+    }
+}
+
 void main() {
     o_v_tex_coord = v_tex_coord;
-    o_b_pos = b_pos;
-    ivec3 clamped_pos = v_pos;
 
-    face = data;
+    // The face, the texture array layer and the light level are packed into the first three bytes
+    // of data.
+    o_normal = mat3(u_transform) * face_normal(data & 0xffu);
+    o_layer = (data >> 8) & 0xffu;
+    o_light = float((data >> 16) & 0xffu) / 255.0;
+
+    ivec3 clamped_pos = v_pos;
 
-    gl_Position = c_transform * u_transform * vec4(clamped_pos, 1.0);
+    vec4 world_pos = u_transform * vec4(clamped_pos, 1.0);
+    o_world_pos = world_pos.xyz;
+    gl_Position = c_transform * world_pos;
 }
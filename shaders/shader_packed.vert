@@ -0,0 +1,44 @@
+#version 450
+
+// Decodes `utils::PackedVertex`. See there for the layout of the two words.
+layout(location = 0) in uvec2 v_packed;
+
+layout(location = 0) out vec2 o_v_tex_coord;
+layout(location = 1) flat out uint o_layer;
+layout(location = 2) out float o_light;
+layout(location = 3) out vec3 o_normal;
+layout(location = 4) out vec3 o_world_pos;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 c_transform;
+};
+
+layout(set = 0, binding = 3) uniform Transform {
+    mat4 u_transform;
+};
+
+// The normal of each face, in the order of `CuboidFaces::ALL`.
+const vec3 FACE_NORMALS[6] = vec3[6](
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 0.0, -1.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.0, -1.0, 0.0),
+    vec3(-1.0, 0.0, 0.0),
+    vec3(1.0, 0.0, 0.0)
+);
+
+void main() {
+    uint lo = v_packed.x;
+    uint hi = v_packed.y;
+
+    vec3 pos = vec3(float(lo & 31u), float((lo >> 5) & 31u), float((lo >> 10) & 31u));
+    o_v_tex_coord = vec2(float((lo >> 20) & 31u), float((lo >> 25) & 31u));
+    o_normal = mat3(u_transform) * FACE_NORMALS[(lo >> 15) & 7u];
+
+    o_layer = hi & 0xffu;
+    o_light = float((hi >> 8) & 0xffu) / 255.0;
+
+    vec4 world_pos = u_transform * vec4(pos, 1.0);
+    o_world_pos = world_pos.xyz;
+    gl_Position = c_transform * world_pos;
+}
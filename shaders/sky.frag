@@ -0,0 +1,43 @@
+#version 450
+
+layout(location = 0) in vec2 v_ndc;
+
+layout(location = 0) out vec4 o_target;
+
+// See `lighting::LightingUniforms`. The fourth component of each member is unused, except for the
+// fog density.
+layout(set = 0, binding = 4) uniform Lighting {
+    vec4 l_sun_direction;
+    vec4 l_sun_color;
+    vec4 l_ambient;
+    vec4 l_zenith_color;
+    vec4 l_fog;
+};
+
+// See `utils::ViewUniforms`.
+layout(set = 0, binding = 8) uniform View {
+    mat4 v_inverse_transform;
+    vec4 v_camera_pos;
+};
+
+// The cosine of the angle from the center of the sun to the edge of its disc.
+const float SUN_DISC_COS = 0.9997;
+// The exponent the cosine of the angle from the center of the sun is raised to for the glow around
+// it. The higher this is, the tighter the glow.
+const float SUN_GLOW_POWER = 200.0;
+
+void main() {
+    // Unproject the pixel onto the far plane to find the direction it looks in.
+    vec4 far = v_inverse_transform * vec4(v_ndc, 1.0, 1.0);
+    vec3 dir = normalize(far.xyz / far.w - v_camera_pos.xyz);
+
+    // Blend from the fog color at the horizon up to the zenith. Below the horizon the sky stays
+    // the fog color, so that it matches the distant fogged terrain.
+    vec3 color = mix(l_fog.rgb, l_zenith_color.rgb, sqrt(max(dir.z, 0.0)));
+
+    // The sun's disc, and the glow around it.
+    float sun = max(dot(dir, l_sun_direction.xyz), 0.0);
+    color += l_sun_color.rgb * (smoothstep(SUN_DISC_COS - 0.0002, SUN_DISC_COS, sun) + 0.5 * pow(sun, SUN_GLOW_POWER));
+
+    o_target = vec4(color, 1.0);
+}
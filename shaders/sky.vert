@@ -0,0 +1,10 @@
+#version 450
+
+// Passed through so the fragment shader can work out which way each pixel looks.
+layout(location = 0) out vec2 o_ndc;
+
+void main() {
+    // A single triangle which covers the whole of clip space: (-1, -1), (3, -1), and (-1, 3).
+    o_ndc = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    gl_Position = vec4(o_ndc, 1.0, 1.0);
+}
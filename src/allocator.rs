@@ -0,0 +1,219 @@
+/// A simple sub-allocator for carving regions out of a larger buffer. The allocator only does the
+/// bookkeeping; it never touches the buffer itself, and all sizes and offsets are in whatever unit
+/// the caller chooses (usually elements of the buffer).
+
+use std::ops::Range;
+
+/// A first-fit free-list allocator over the range `0..capacity`.
+pub struct RangeAllocator {
+    capacity: usize,
+    /// The unallocated ranges. These are kept sorted, and merged so that no two ranges overlap or
+    /// touch.
+    free: Vec<Range<usize>>,
+}
+
+/// A snapshot of how much of a `RangeAllocator` is in use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub capacity: usize,
+    pub used: usize,
+    pub free: usize,
+    /// The size of the largest allocation which would currently succeed.
+    pub largest_free: usize,
+    /// The number of separate holes the free space is split into.
+    pub free_ranges: usize,
+}
+
+impl std::fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} used, {} free in {} ranges (largest {})",
+            self.used, self.capacity, self.free, self.free_ranges, self.largest_free,
+        )
+    }
+}
+
+#[allow(dead_code)]
+impl RangeAllocator {
+    /// Create a new `RangeAllocator` with everything in `0..capacity` free.
+    pub fn new(capacity: usize) -> Self {
+        let mut allocator = Self {
+            capacity,
+            free: Vec::new(),
+        };
+        allocator.reset();
+        allocator
+    }
+
+    /// Returns the size of the range managed by this allocator.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Allocate a range of `size` units, returning `None` if there is no free range large enough.
+    /// The lowest free range which fits is used.
+    pub fn allocate(&mut self, size: usize) -> Option<Range<usize>> {
+        if size == 0 {
+            return None;
+        }
+
+        let i = self.free.iter().position(|r| r.end - r.start >= size)?;
+        let start = self.free[i].start;
+        if self.free[i].end - start == size {
+            self.free.remove(i);
+        } else {
+            self.free[i].start += size;
+        }
+
+        Some(start..start + size)
+    }
+
+    /// Returns a previously allocated range to the allocator.
+    ///
+    /// N.B. This function will panic if the range overlaps any free range, which means it was freed
+    /// twice or never allocated.
+    pub fn free(&mut self, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        // The index of the first free range after the one being freed.
+        let i = self.free.iter().position(|r| r.start >= range.end).unwrap_or(self.free.len());
+        assert!(
+            i == 0 || self.free[i - 1].end <= range.start,
+            "freed range {:?} is not allocated",
+            range,
+        );
+
+        let touches_prev = i > 0 && self.free[i - 1].end == range.start;
+        let touches_next = i < self.free.len() && self.free[i].start == range.end;
+        match (touches_prev, touches_next) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            },
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+
+    /// Extend the range managed by this allocator to `0..capacity`. The allocator is never shrunk.
+    pub fn grow(&mut self, capacity: usize) {
+        if capacity <= self.capacity {
+            return;
+        }
+
+        match self.free.last_mut() {
+            Some(last) if last.end == self.capacity => last.end = capacity,
+            _ => self.free.push(self.capacity..capacity),
+        }
+        self.capacity = capacity;
+    }
+
+    /// Frees every allocation at once.
+    pub fn reset(&mut self) {
+        self.free.clear();
+        if self.capacity > 0 {
+            self.free.push(0..self.capacity);
+        }
+    }
+
+    /// Returns a snapshot of how much of the allocator is in use.
+    pub fn stats(&self) -> AllocatorStats {
+        let free = self.free.iter().map(|r| r.end - r.start).sum();
+        AllocatorStats {
+            capacity: self.capacity,
+            used: self.capacity - free,
+            free,
+            largest_free: self.free.iter().map(|r| r.end - r.start).max().unwrap_or(0),
+            free_ranges: self.free.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_first_fit() {
+        let mut allocator = RangeAllocator::new(16);
+        assert_eq!(allocator.allocate(4), Some(0..4));
+        assert_eq!(allocator.allocate(4), Some(4..8));
+        allocator.free(0..4);
+        assert_eq!(allocator.allocate(2), Some(0..2));
+        assert_eq!(allocator.allocate(4), Some(8..12));
+        assert_eq!(allocator.allocate(8), None);
+        assert_eq!(allocator.allocate(0), None);
+    }
+
+    #[test]
+    fn freed_ranges_coalesce_with_their_neighbors() {
+        let mut allocator = RangeAllocator::new(16);
+        let ranges: Vec<_> = (0..4).map(|_| allocator.allocate(4).unwrap()).collect();
+        assert_eq!(allocator.stats().free_ranges, 0);
+
+        // Neither neighbor is free.
+        allocator.free(ranges[1].clone());
+        assert_eq!(allocator.free, vec![4..8]);
+        // Touches the free range before it.
+        allocator.free(ranges[2].clone());
+        assert_eq!(allocator.free, vec![4..12]);
+        // Touches the free range after it.
+        allocator.free(ranges[0].clone());
+        assert_eq!(allocator.free, vec![0..12]);
+        // Touches free ranges on both sides, once the middle is split off again.
+        assert_eq!(allocator.allocate(12), Some(0..12));
+        allocator.free(0..4);
+        allocator.free(8..16);
+        assert_eq!(allocator.free, vec![0..4, 8..16]);
+        allocator.free(4..8);
+        assert_eq!(allocator.free, vec![0..16]);
+        assert_eq!(allocator.stats(), AllocatorStats {
+            capacity: 16,
+            used: 0,
+            free: 16,
+            largest_free: 16,
+            free_ranges: 1,
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "is not allocated")]
+    fn freeing_twice_panics() {
+        let mut allocator = RangeAllocator::new(16);
+        let range = allocator.allocate(4).unwrap();
+        allocator.free(range.clone());
+        allocator.free(range);
+    }
+
+    #[test]
+    fn growing_extends_the_last_free_range() {
+        // The end of the old capacity is free, so the new space joins it.
+        let mut allocator = RangeAllocator::new(16);
+        allocator.allocate(8).unwrap();
+        allocator.grow(32);
+        assert_eq!(allocator.free, vec![8..32]);
+        assert_eq!(allocator.allocate(24), Some(8..32));
+
+        // The end of the old capacity is allocated, so the new space is a range of its own.
+        allocator.grow(40);
+        assert_eq!(allocator.free, vec![32..40]);
+        allocator.free(0..8);
+        assert_eq!(allocator.free, vec![0..8, 32..40]);
+
+        // Shrinking is ignored.
+        allocator.grow(4);
+        assert_eq!(allocator.capacity(), 40);
+    }
+
+    #[test]
+    fn growing_an_empty_allocator() {
+        let mut allocator = RangeAllocator::new(0);
+        assert_eq!(allocator.allocate(1), None);
+        allocator.grow(8);
+        assert_eq!(allocator.allocate(8), Some(0..8));
+    }
+}
@@ -0,0 +1,161 @@
+/// Block types and the registry which describes them. Voxel data only stores `BlockId`s; everything
+/// else we know about a block (what it is called, whether it can be seen through, how each of its
+/// faces is textured) is looked up here.
+
+use std::collections::HashMap;
+
+use crate::texture_array::{TextureArray, TextureError};
+use crate::mesh::CuboidFaces;
+
+/// Identifies the type of a block. This is an index into a `BlockRegistry`.
+pub type BlockId = u16;
+
+// The IDs of the built-in blocks. These are registered, in this order, by `BlockRegistry::new`.
+
+/// The block ID reserved for empty space.
+pub const AIR: BlockId = 0;
+pub const SNOW: BlockId = 1;
+pub const WATER: BlockId = 2;
+pub const DIRT: BlockId = 3;
+pub const GRASS: BlockId = 4;
+
+/// Describes a single type of block.
+#[derive(Clone, Debug)]
+pub struct BlockDefinition {
+    pub name: &'static str,
+    /// Solid blocks have geometry; non-solid blocks (like air) are never meshed.
+    pub solid: bool,
+    /// Transparent blocks do not hide the faces of the blocks next to them.
+    pub transparent: bool,
+    /// The name of the texture drawn on each face, in the order of `CuboidFaces::ALL`. Non-solid
+    /// blocks are never drawn, so their textures are ignored.
+    pub textures: [&'static str; 6],
+    /// The texture array layer drawn on each face. These are looked up from `textures` when the
+    /// block is registered.
+    layers: [u32; 6],
+}
+
+impl BlockDefinition {
+    /// Create a block which has no geometry, and so is never drawn.
+    pub fn empty(name: &'static str) -> Self {
+        Self {
+            name,
+            solid: false,
+            transparent: true,
+            textures: [""; 6],
+            layers: [0; 6],
+        }
+    }
+
+    /// Create a solid, opaque block which uses the same texture on every face.
+    pub fn uniform(name: &'static str, texture: &'static str) -> Self {
+        Self {
+            name,
+            solid: true,
+            transparent: false,
+            textures: [texture; 6],
+            layers: [0; 6],
+        }
+    }
+
+    /// Create a solid, opaque block with separate textures for its top, its sides, and its bottom.
+    pub fn top_side_bottom(
+        name: &'static str,
+        top: &'static str,
+        side: &'static str,
+        bottom: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            solid: true,
+            transparent: false,
+            textures: [top, bottom, side, side, side, side],
+            layers: [0; 6],
+        }
+    }
+
+    /// Returns the texture array layer drawn on a single face of this block.
+    pub fn layer(&self, face: CuboidFaces) -> u32 {
+        self.layers[face.index()]
+    }
+
+    /// Returns true if this block hides the faces of the blocks next to it.
+    pub fn is_opaque(&self) -> bool {
+        self.solid && !self.transparent
+    }
+}
+
+pub struct BlockRegistry {
+    blocks: Vec<BlockDefinition>,
+    ids: HashMap<&'static str, BlockId>,
+
+    /// The texture array layer of every texture, keyed by name.
+    texture_layers: HashMap<String, u32>,
+}
+
+#[allow(dead_code)]
+impl BlockRegistry {
+    /// Create a new `BlockRegistry` holding the built-in blocks, textured from `textures`. Fails if
+    /// any of the textures the built-in blocks use is missing.
+    pub fn new(textures: &TextureArray) -> Result<Self, TextureError> {
+        let mut registry = Self {
+            blocks: Vec::new(),
+            ids: HashMap::new(),
+            texture_layers: textures.layers().map(|(name, layer)| (name.to_owned(), layer)).collect(),
+        };
+
+        registry.register(BlockDefinition::empty("air"))?;
+        registry.register(BlockDefinition::uniform("snow", "snow"))?;
+        registry.register(BlockDefinition::uniform("water", "water"))?;
+        registry.register(BlockDefinition::uniform("dirt", "dirt"))?;
+        registry.register(BlockDefinition::top_side_bottom("grass", "grass", "dirt", "dirt"))?;
+
+        debug_assert_eq!(registry.id("grass"), Some(GRASS));
+
+        Ok(registry)
+    }
+
+    /// Adds a block definition to the registry, returning the ID assigned to it. Fails if the
+    /// block is solid, and any of its textures was not loaded.
+    ///
+    /// N.B. This function will panic if a block with the same name is already registered.
+    pub fn register(&mut self, mut definition: BlockDefinition) -> Result<BlockId, TextureError> {
+        if definition.solid {
+            for (layer, texture) in definition.layers.iter_mut().zip(definition.textures.iter()) {
+                *layer = *self
+                    .texture_layers
+                    .get(*texture)
+                    .ok_or_else(|| TextureError::MissingTexture(texture.to_string()))?;
+            }
+        }
+
+        let id = self.blocks.len() as BlockId;
+        if self.ids.insert(definition.name, id).is_some() {
+            panic!("block `{}` registered twice", definition.name);
+        }
+        self.blocks.push(definition);
+        Ok(id)
+    }
+
+    /// Returns the definition of a block.
+    ///
+    /// N.B. This function will panic if `id` was not handed out by this registry.
+    pub fn get(&self, id: BlockId) -> &BlockDefinition {
+        &self.blocks[id as usize]
+    }
+
+    /// Looks up the ID of a block by name.
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.ids.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+impl BlockRegistry {
+    /// Create a registry of the built-in blocks, textured from the repository's own textures.
+    pub fn for_tests() -> Self {
+        let textures = TextureArray::load(concat!(env!("CARGO_MANIFEST_DIR"), "/textures"))
+            .expect("failed to load the block textures");
+        Self::new(&textures).expect("a built-in block's texture is missing")
+    }
+}
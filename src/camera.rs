@@ -75,6 +75,11 @@ impl Camera {
         }
     }
 
+    /// Returns the view frustum of the camera, for culling geometry which cannot be seen.
+    pub fn frustum(&mut self) -> Frustum {
+        Frustum::from_matrix(self.matrix())
+    }
+
     // Requires a mutable reference since this function caches its results. This might be okay.
     fn right(&mut self) -> cgmath::Vector3<f32> {
         if let Some(cached_cross) = self.cached_right {
@@ -92,48 +97,61 @@ impl Camera {
         self.cached_right = None;
     }
 
-    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
-        self.invalidate_cache();
-
-        self.aspect_ratio = aspect_ratio;
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        self.position
     }
 
-    pub fn move_forward(&mut self, mag: f32) {
-        self.invalidate_cache();
-
-        self.position += self.view * mag;
+    /// Returns the distances to the near and far clip planes.
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.z_near, self.z_far)
     }
 
-    pub fn move_backward(&mut self, mag: f32) {
-        self.invalidate_cache();
-
-        self.position -= self.view * mag;
-    }
-
-    pub fn move_up(&mut self, mag: f32) {
-        self.invalidate_cache();
-
-        self.position += self.up * mag;
-    }
-
-    pub fn move_down(&mut self, mag: f32) {
-        self.invalidate_cache();
+    /// Returns the corners of the view frustum in world space, cut short at `max_distance` from
+    /// the camera if that is nearer than the far plane. The four corners of the near plane come
+    /// first, followed by the four of the far plane.
+    pub fn frustum_corners(&mut self, max_distance: f32) -> [cgmath::Point3<f32>; 8] {
+        let right = self.right().normalize();
+        let up = right.cross(self.view);
+        let tan_y = (cgmath::Deg(self.fovy) / 2.0).tan();
+
+        let mut corners = [self.position; 8];
+        for (i, &distance) in [self.z_near, max_distance.min(self.z_far)].iter().enumerate() {
+            let center = self.position + self.view * distance;
+            let half_height = up * (distance * tan_y);
+            let half_width = right * (distance * tan_y * self.aspect_ratio);
+            corners[4 * i] = center - half_width - half_height;
+            corners[4 * i + 1] = center + half_width - half_height;
+            corners[4 * i + 2] = center + half_width + half_height;
+            corners[4 * i + 3] = center - half_width + half_height;
+        }
 
-        self.position -= self.up * mag;
+        corners
     }
 
-    pub fn move_right(&mut self, mag: f32) {
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
         self.invalidate_cache();
 
-        let r = self.right();
-        self.position += r * mag;
+        self.aspect_ratio = aspect_ratio;
     }
 
-    pub fn move_left(&mut self, mag: f32) {
+    /// Moves the camera `mag` units in a direction given relative to the way it is facing, as
+    /// amounts forward, right, and up. Forward and right stay level with the ground, whatever the
+    /// pitch, and up is straight up the z-axis. The direction is normalized, so moving diagonally is
+    /// no faster than moving straight. A zero direction doesn't move the camera.
+    pub fn move_along(&mut self, direction: cgmath::Vector3<f32>, mag: f32) {
+        let up = cgmath::Vector3::unit_z();
+        // Looking straight up or down, there is no way to tell which way is forward.
+        let level = cgmath::Vector3::new(self.view.x, self.view.y, 0.0);
+        let forward = if level.magnitude2() > 0.0 { level.normalize() } else { cgmath::Vector3::zero() };
+        let right = forward.cross(up);
+
+        let d = forward * direction.x + right * direction.y + up * direction.z;
+        if d.magnitude2() == 0.0 {
+            return;
+        }
         self.invalidate_cache();
 
-        let r = self.right();
-        self.position -= r * mag;
+        self.position += d.normalize() * mag;
     }
 
     pub fn rotate_by_x_y(
@@ -174,3 +192,88 @@ impl Camera {
         self.view = x_quat.rotate_vector(self.view).normalize();
     }
 }
+
+/// A view frustum, described by six planes whose normals point into the frustum.
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extract the frustum planes from a view projection matrix. The matrix is expected to map into
+    /// wgpu's clip space, where depth ranges from 0 to 1.
+    pub fn from_matrix(m: cgmath::Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+
+        Self {
+            planes: [
+                r3 + r0, // left
+                r3 - r0, // right
+                r3 + r1, // bottom
+                r3 - r1, // top
+                r2,      // near
+                r3 - r2, // far
+            ],
+        }
+    }
+
+    /// Returns true if any part of the axis aligned box described by `min` and `max` may lie inside
+    /// of the frustum. This is conservative; some boxes just outside of the corners of the frustum
+    /// will be reported as intersecting it.
+    pub fn intersects_aabb(&self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // Test the corner of the box furthest along the plane's normal. If even that corner is
+            // behind the plane, the whole box is.
+            let corner = cgmath::Vector4::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+                1.0,
+            );
+            plane.dot(corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Point3, Vector3};
+
+    /// Returns how far the camera moves for a single call to `move_along`.
+    fn displacement(camera: &mut Camera, direction: Vector3<f32>) -> Vector3<f32> {
+        let before = camera.position();
+        camera.move_along(direction, 1.0);
+        camera.position() - before
+    }
+
+    #[test]
+    fn diagonal_movement_is_level_and_halfway_between_the_axes() {
+        // Looking up and to the side, so that the view is tilted and off the axes.
+        let view = Vector3::new(2.0, 1.0, 1.5);
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 0.0), view, Vector3::unit_z(), 1.0, 1.0, 0.1, 100.0);
+
+        let forward = displacement(&mut camera, Vector3::new(1.0, 0.0, 0.0));
+        let right = displacement(&mut camera, Vector3::new(0.0, 1.0, 0.0));
+        let diagonal = displacement(&mut camera, Vector3::new(1.0, 1.0, 0.0));
+
+        for d in &[forward, right, diagonal] {
+            assert!((d.magnitude() - 1.0).abs() < 1e-5, "moved {:?}", d);
+            assert!(d.z.abs() < 1e-5, "moved {:?}", d);
+        }
+        assert!(forward.dot(right).abs() < 1e-5);
+        assert!(forward.dot(view) > 0.0);
+        // 45 degrees from both forward and right.
+        let cos_45 = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((diagonal.dot(forward) - cos_45).abs() < 1e-5, "moved {:?}", diagonal);
+        assert!((diagonal.dot(right) - cos_45).abs() < 1e-5, "moved {:?}", diagonal);
+    }
+
+    #[test]
+    fn moving_up_is_straight_up() {
+        let view = Vector3::new(1.0, 0.0, -2.0);
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 0.0), view, Vector3::unit_z(), 1.0, 1.0, 0.1, 100.0);
+        let d = displacement(&mut camera, Vector3::new(1.0, 0.0, 1.0));
+        let cos_45 = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((d - Vector3::new(cos_45, 0.0, cos_45)).magnitude() < 1e-5, "moved {:?}", d);
+    }
+}
@@ -0,0 +1,126 @@
+/// Voxel storage for world geometry. A `Chunk` is a dense cube of block IDs; it knows nothing about
+/// where it sits in the world or how it will be drawn. Generation fills chunks, and meshing reads
+/// them back out to build GPU geometry.
+
+use std::sync::Arc;
+
+use cgmath::{Point3, Vector3};
+
+use crate::block::{AIR, BlockId};
+
+/// Chunks are cubes of voxels, and this value is the size of the cube.
+pub const CHUNK_DIM: usize = 16;
+
+/// The number of voxels held by a single chunk.
+pub const CHUNK_VOLUME: usize = CHUNK_DIM * CHUNK_DIM * CHUNK_DIM;
+
+#[derive(Clone)]
+pub struct Chunk {
+    /// Block IDs laid out with z varying fastest, then y, then x. Most of the world is open sky, so
+    /// this is not allocated until the first non-air block is written.
+    blocks: Option<Box<[BlockId; CHUNK_VOLUME]>>,
+}
+
+#[allow(dead_code)]
+impl Chunk {
+    /// Create a new `Chunk` filled entirely with air.
+    pub fn new() -> Self {
+        Self {
+            blocks: None,
+        }
+    }
+
+    /// Returns true if the chunk-local position lies inside the chunk.
+    #[inline(always)]
+    pub fn in_bounds(pos: Point3<i32>) -> bool {
+        let dim = CHUNK_DIM as i32;
+        pos.x >= 0 && pos.x < dim && pos.y >= 0 && pos.y < dim && pos.z >= 0 && pos.z < dim
+    }
+
+    #[inline(always)]
+    fn index(pos: Point3<i32>) -> usize {
+        (pos.x as usize * CHUNK_DIM + pos.y as usize) * CHUNK_DIM + pos.z as usize
+    }
+
+    /// Returns the block at the chunk-local position, or `None` if the position lies outside of the
+    /// chunk.
+    pub fn get(&self, pos: Point3<i32>) -> Option<BlockId> {
+        if Self::in_bounds(pos) {
+            Some(self.blocks.as_ref().map_or(AIR, |blocks| blocks[Self::index(pos)]))
+        } else {
+            None
+        }
+    }
+
+    /// Sets the block at the chunk-local position, returning the block that was there before. If
+    /// the position lies outside of the chunk, nothing is written and `None` is returned.
+    pub fn set(&mut self, pos: Point3<i32>, block: BlockId) -> Option<BlockId> {
+        if !Self::in_bounds(pos) {
+            return None;
+        }
+        if self.blocks.is_none() && block == AIR {
+            return Some(AIR);
+        }
+
+        let blocks = self.blocks.get_or_insert_with(|| Box::new([AIR; CHUNK_VOLUME]));
+        Some(std::mem::replace(&mut blocks[Self::index(pos)], block))
+    }
+
+    /// Returns true if every block in the chunk is air.
+    pub fn is_empty(&self) -> bool {
+        match &self.blocks {
+            Some(blocks) => blocks.iter().all(|&b| b == AIR),
+            None => true,
+        }
+    }
+}
+
+/// A chunk together with the 26 chunks surrounding it. This is everything the mesher needs to see
+/// in order to decide which faces on the border of a chunk are exposed.
+pub struct ChunkNeighborhood {
+    /// Indexed by the offset of each chunk from the center chunk; see `ChunkNeighborhood::slot`.
+    chunks: [Option<Arc<Chunk>>; 27],
+}
+
+#[allow(dead_code)]
+impl ChunkNeighborhood {
+    /// Gather the neighborhood of the chunk at `center`, looking chunks up by chunk coordinates with
+    /// `lookup`. Returns `None` if any of the 27 chunks are missing.
+    pub fn gather<F>(center: Point3<i32>, lookup: F) -> Option<Self>
+        where F: Fn(Point3<i32>) -> Option<Arc<Chunk>>
+    {
+        let mut chunks: [Option<Arc<Chunk>>; 27] = Default::default();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let offset = Vector3::new(dx, dy, dz);
+                    chunks[Self::slot(offset)] = Some(lookup(center + offset)?);
+                }
+            }
+        }
+
+        Some(Self { chunks })
+    }
+
+    #[inline(always)]
+    fn slot(offset: Vector3<i32>) -> usize {
+        ((offset.x + 1) * 9 + (offset.y + 1) * 3 + (offset.z + 1)) as usize
+    }
+
+    /// Returns the center chunk.
+    pub fn center(&self) -> &Chunk {
+        self.chunks[Self::slot(Vector3::new(0, 0, 0))].as_ref().unwrap()
+    }
+
+    /// Returns the block at a position local to the center chunk. The position may lie anywhere in
+    /// the surrounding chunks; `None` is returned for anything further out.
+    pub fn get(&self, pos: Point3<i32>) -> Option<BlockId> {
+        let dim = CHUNK_DIM as i32;
+        let offset = Vector3::new(pos.x.div_euclid(dim), pos.y.div_euclid(dim), pos.z.div_euclid(dim));
+        if offset.x.abs() > 1 || offset.y.abs() > 1 || offset.z.abs() > 1 {
+            return None;
+        }
+
+        self.chunks[Self::slot(offset)].as_ref()?.get(pos - offset * dim)
+    }
+}
@@ -0,0 +1,223 @@
+/// A pool of worker threads which generate and mesh chunks away from the render thread. Jobs are
+/// queued with the pool, and their results come back over a channel to be picked up by the
+/// `WorldGeometryManager` whenever it is ready for them.
+
+use std::cell::Cell;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use cgmath::Point3;
+
+use crate::block::BlockRegistry;
+use crate::chunk::{Chunk, ChunkNeighborhood};
+use crate::simplex;
+use crate::terrain::{self, TerrainSampler};
+use crate::world_geometry::{self, Mesher};
+
+pub enum ChunkJob {
+    /// Fill the chunk at this position with terrain.
+    Generate(Point3<i32>),
+    /// Build the mesh for the center chunk of the neighborhood. The version is handed back with the
+    /// result, so that results which were overtaken by a later request can be recognized.
+    Mesh {
+        position: Point3<i32>,
+        version: u32,
+        neighborhood: ChunkNeighborhood,
+        mesher: Mesher,
+    },
+}
+
+impl ChunkJob {
+    /// Returns the position of the chunk this job is working on.
+    pub fn position(&self) -> Point3<i32> {
+        match self {
+            ChunkJob::Generate(position) => *position,
+            ChunkJob::Mesh { position, .. } => *position,
+        }
+    }
+}
+
+pub enum ChunkResult {
+    Generated {
+        position: Point3<i32>,
+        chunk: Chunk,
+    },
+    Meshed {
+        position: Point3<i32>,
+        version: u32,
+        vertices: Vec<world_geometry::Vertex>,
+        indices: Vec<world_geometry::Index>,
+    },
+}
+
+struct JobQueue {
+    /// Jobs waiting to be picked up by a worker. This is kept sorted with the job nearest to the
+    /// camera last, so that it is the next to be popped.
+    jobs: Vec<ChunkJob>,
+    shutdown: bool,
+}
+
+pub struct ChunkWorkerPool {
+    queue: Arc<(Mutex<JobQueue>, Condvar)>,
+    results: mpsc::Receiver<ChunkResult>,
+    workers: Vec<thread::JoinHandle<()>>,
+    /// The center which the queued jobs were last sorted around.
+    sorted_center: Cell<Option<Point3<i32>>>,
+}
+
+impl ChunkWorkerPool {
+    /// Create a new `ChunkWorkerPool`, spawning `worker_count` worker threads.
+    pub fn new(
+        worker_count: usize,
+        noise: Arc<simplex::Simplex>,
+        registry: Arc<BlockRegistry>,
+    ) -> Self {
+        let queue = Arc::new((
+            Mutex::new(JobQueue {
+                jobs: Vec::new(),
+                shutdown: false,
+            }),
+            Condvar::new(),
+        ));
+        let (sender, results) = mpsc::channel();
+
+        let workers = (0..worker_count.max(1))
+            .map(|i| {
+                let queue = queue.clone();
+                let sender = sender.clone();
+                let noise = noise.clone();
+                let registry = registry.clone();
+                thread::Builder::new()
+                    .name(format!("chunk worker {}", i))
+                    .spawn(move || worker_loop(&queue, &sender, &noise, &registry))
+                    .expect("failed to spawn chunk worker thread")
+            })
+            .collect();
+
+        Self {
+            queue,
+            results,
+            workers,
+            sorted_center: Cell::new(None),
+        }
+    }
+
+    /// Queue jobs with the pool, so that the jobs nearest to `center` are picked up first. The
+    /// queue is only reordered when there are new jobs, or `center` has moved; otherwise the
+    /// workers are left alone.
+    pub fn request<I>(&self, center: Point3<i32>, jobs: I)
+        where I: IntoIterator<Item = ChunkJob>
+    {
+        let distance = |job: &ChunkJob| {
+            let d = job.position() - center;
+            std::cmp::Reverse(d.x * d.x + d.y * d.y + d.z * d.z)
+        };
+
+        let mut jobs: Vec<_> = jobs.into_iter().collect();
+        let moved = self.sorted_center.get() != Some(center);
+        if jobs.is_empty() && !moved {
+            return;
+        }
+        self.sorted_center.set(Some(center));
+        // Sort the new jobs before taking the lock. If the center hasn't moved, the queue is then
+        // two sorted runs, which the sort below merges in a single pass.
+        jobs.sort_by_key(distance);
+
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+
+        let added = jobs.len();
+        queue.jobs.extend(jobs);
+        queue.jobs.sort_by_key(distance);
+
+        match added {
+            0 => {},
+            1 => condvar.notify_one(),
+            _ => condvar.notify_all(),
+        }
+    }
+
+    /// Remove every queued job whose chunk position `should_cancel` returns true for, returning the
+    /// cancelled jobs. Jobs which a worker has already picked up cannot be cancelled, and their
+    /// results will still be returned by `try_recv`.
+    pub fn cancel<F>(&self, should_cancel: F) -> Vec<ChunkJob>
+        where F: Fn(Point3<i32>) -> bool
+    {
+        let (lock, _) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+
+        let (cancelled, kept) = queue.jobs.drain(..).partition(|job| should_cancel(job.position()));
+        queue.jobs = kept;
+
+        cancelled
+    }
+
+    /// Returns a finished chunk, if any are waiting. This never blocks.
+    pub fn try_recv(&self) -> Option<ChunkResult> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for ChunkWorkerPool {
+    fn drop(&mut self) {
+        {
+            let (lock, condvar) = &*self.queue;
+            lock.lock().unwrap().shutdown = true;
+            condvar.notify_all();
+        }
+
+        for worker in self.workers.drain(..) {
+            // A worker which panicked has already reported it; there is nothing more to do here.
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_loop(
+    queue: &(Mutex<JobQueue>, Condvar),
+    results: &mpsc::Sender<ChunkResult>,
+    noise: &simplex::Simplex,
+    registry: &BlockRegistry,
+) {
+    let (lock, condvar) = queue;
+    loop {
+        // Wait for a job, or for the pool to shut down.
+        let job = {
+            let mut queue = lock.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(job) = queue.jobs.pop() {
+                    break job;
+                }
+                queue = condvar.wait(queue).unwrap();
+            }
+        };
+
+        let result = match job {
+            ChunkJob::Generate(position) => {
+                let sampler = TerrainSampler::new(position, noise);
+                let chunk = crate::benchmark!("chunk generation", terrain::generate_chunk(&sampler));
+                ChunkResult::Generated { position, chunk }
+            },
+            ChunkJob::Mesh { position, version, neighborhood, mesher } => {
+                let mesh = crate::benchmark!(
+                    "chunk meshing",
+                    world_geometry::mesh_chunk(&neighborhood, registry, mesher).report()
+                );
+                // Rather than draw garbage, leave a chunk whose mesh overflowed empty.
+                let (vertices, indices) = mesh.unwrap_or_else(|e| {
+                    log::error!("Failed to mesh chunk {:?}: {}", position, e);
+                    (Vec::new(), Vec::new())
+                });
+                ChunkResult::Meshed { position, version, vertices, indices }
+            },
+        };
+
+        // If the receiver is gone, the pool is being torn down.
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
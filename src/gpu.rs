@@ -1,19 +1,58 @@
+/// The format of every frame rendered by a `GpuContext`, whether it is presented to a window or
+/// not.
+pub const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// Rows of a texture copied into a buffer must be padded out to a multiple of this many bytes.
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
 /// This struct is the base level abstraction to the GPU. It is responsible for managing the render
-/// surface, the swap chain, the device, and the device queue.
+/// target, the device, and the device queue.
 pub struct GpuContext {
     // TODO: does this field need to be here?
     #[allow(dead_code)]
     instance: wgpu::Instance,
 
-    surface: wgpu::Surface,
     // TODO: does this field need to be here?
     #[allow(dead_code)]
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
 
-    swap_chain_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
+    target: RenderTarget,
+}
+
+/// Where the frames rendered by a `GpuContext` end up.
+enum RenderTarget {
+    /// Frames are presented to a window through its swap chain.
+    Window {
+        surface: wgpu::Surface,
+        swap_chain_desc: wgpu::SwapChainDescriptor,
+        swap_chain: wgpu::SwapChain,
+    },
+    /// Frames are rendered into a texture which is never presented, but can be read back with
+    /// `read_pixels`.
+    Offscreen {
+        texture: wgpu::Texture,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// A frame to render into, as returned by `GpuContext::get_next_frame`. A swap chain frame is
+/// presented when it is dropped.
+pub enum Frame {
+    SwapChain(wgpu::SwapChainFrame),
+    Offscreen(wgpu::TextureView),
+}
+
+impl Frame {
+    /// Returns the view to use as the color attachment when rendering this frame.
+    pub fn view(&self) -> &wgpu::TextureView {
+        match self {
+            Frame::SwapChain(frame) => &frame.output.view,
+            Frame::Offscreen(view) => view,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -21,6 +60,9 @@ pub enum GpuContextError {
     RequestAdapterError,
     RequestDeviceError(wgpu::RequestDeviceError),
     SwapChainError(wgpu::SwapChainError),
+    /// Pixels can only be read back from a headless `GpuContext`.
+    NotOffscreenError,
+    BufferMapError(wgpu::BufferAsyncError),
 }
 
 impl std::fmt::Display for GpuContextError {
@@ -32,6 +74,10 @@ impl std::fmt::Display for GpuContextError {
                 write!(f, "Device request failed! `wgpu` error is: {:?}", wgpu_err),
             GpuContextError::SwapChainError(wgpu_err) =>
                 write!(f, "Swap chain operation failed! `wgpu` error is: {:?}", wgpu_err),
+            GpuContextError::NotOffscreenError =>
+                write!(f, "Pixels can only be read from an offscreen render target."),
+            GpuContextError::BufferMapError(wgpu_err) =>
+                write!(f, "Buffer mapping failed! `wgpu` error is: {:?}", wgpu_err),
         }
     }
 }
@@ -43,10 +89,6 @@ impl GpuContext {
     pub async fn create(window: &winit::window::Window) -> Result<GpuContext, GpuContextError> {
         let size = window.inner_size();
 
-        // TODO: These features will eventually need to be parameters.
-        let unsafe_features = wgpu::UnsafeFeatures::disallow();
-        let required_features = wgpu::Features::empty();
-
         // Create the wgpu instance.
         let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
 
@@ -60,27 +102,18 @@ impl GpuContext {
                     power_preference: wgpu::PowerPreference::HighPerformance,
                     compatible_surface: Some(&surface),
                 },
-                unsafe_features,
+                wgpu::UnsafeFeatures::disallow(),
             )
             .await
             .ok_or(GpuContextError::RequestAdapterError)?;
 
-        let adapter_features = adapter.features();
-
-        // Create the device handle and the command queue handle for that device.
-        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
-            features: adapter_features & required_features,
-            limits: wgpu::Limits::default(),
-            shader_validation: true,
-        }, None)
-        .await
-        .map_err(|e| GpuContextError::RequestDeviceError(e))?;
+        let (device, queue) = request_device(&adapter).await?;
 
         // Create our swapchain. The swapchain is an abstraction over a buffered pixel array which corresponds directly
         // to the image which is rendered onto the display.
         let swap_chain_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            format: FRAME_FORMAT,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Mailbox,
@@ -90,12 +123,51 @@ impl GpuContext {
 
         Ok(Self {
             instance,
-            surface,
             adapter,
             device,
             queue,
-            swap_chain_desc,
-            swap_chain,
+            target: RenderTarget::Window {
+                surface,
+                swap_chain_desc,
+                swap_chain,
+            },
+        })
+    }
+
+    /// Create a new `GpuContext` which renders into an offscreen texture of the given size, rather
+    /// than a window. No display is needed, and the rendered frames can be read back with
+    /// `read_pixels`.
+    ///
+    /// Since there is no surface to be compatible with, any adapter will do. If none of the primary
+    /// backends have an adapter, every backend is tried, which lets software adapters like llvmpipe
+    /// or SwiftShader stand in for a real GPU.
+    pub async fn create_headless(width: u32, height: u32) -> Result<GpuContext, GpuContextError> {
+        let options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::Default,
+            compatible_surface: None,
+        };
+
+        let mut instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let mut adapter = instance.request_adapter(&options, wgpu::UnsafeFeatures::disallow()).await;
+        if adapter.is_none() {
+            instance = wgpu::Instance::new(wgpu::BackendBit::all());
+            adapter = instance.request_adapter(&options, wgpu::UnsafeFeatures::disallow()).await;
+        }
+        let adapter = adapter.ok_or(GpuContextError::RequestAdapterError)?;
+
+        let (device, queue) = request_device(&adapter).await?;
+        let texture = create_offscreen_texture(&device, width, height);
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            target: RenderTarget::Offscreen {
+                texture,
+                width,
+                height,
+            },
         })
     }
 
@@ -103,10 +175,28 @@ impl GpuContext {
     // Functions with logic.
     //
 
+    /// Recreate the swap chain with its current description. This must be done whenever the swap
+    /// chain has become outdated or been lost. Offscreen render targets are never lost, so this
+    /// does nothing for them.
+    pub fn recreate_swap_chain(&mut self) {
+        if let RenderTarget::Window { surface, swap_chain_desc, swap_chain } = &mut self.target {
+            *swap_chain = self.device.create_swap_chain(surface, swap_chain_desc);
+        }
+    }
+
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
-        self.swap_chain_desc.width = size.width;
-        self.swap_chain_desc.height = size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.swap_chain_desc);
+        match &mut self.target {
+            RenderTarget::Window { surface, swap_chain_desc, swap_chain } => {
+                swap_chain_desc.width = size.width;
+                swap_chain_desc.height = size.height;
+                *swap_chain = self.device.create_swap_chain(surface, swap_chain_desc);
+            },
+            RenderTarget::Offscreen { texture, width, height } => {
+                *width = size.width;
+                *height = size.height;
+                *texture = create_offscreen_texture(&self.device, size.width, size.height);
+            },
+        }
     }
 
     pub fn create_command_encoder(&self) -> wgpu::CommandEncoder {
@@ -119,14 +209,95 @@ impl GpuContext {
         self.device.create_shader_module(spirv)
     }
 
-    pub fn get_next_frame(&mut self) -> Result<wgpu::SwapChainFrame, GpuContextError> {
-        self.swap_chain.get_next_frame().map_err(|e| GpuContextError::SwapChainError(e))
+    pub fn get_next_frame(&mut self) -> Result<Frame, GpuContextError> {
+        match &mut self.target {
+            RenderTarget::Window { swap_chain, .. } => swap_chain
+                .get_next_frame()
+                .map(Frame::SwapChain)
+                .map_err(|e| GpuContextError::SwapChainError(e)),
+            RenderTarget::Offscreen { texture, .. } => Ok(Frame::Offscreen(texture.create_default_view())),
+        }
     }
 
     pub fn submit_command_encoder(&self, encoder: wgpu::CommandEncoder) {
         self.queue.submit(Some(encoder.finish()));
     }
 
+    /// Copies the last frame rendered by a headless `GpuContext` back from the GPU. This blocks
+    /// until every command submitted so far has finished executing.
+    pub fn read_pixels(&self) -> Result<image::RgbaImage, GpuContextError> {
+        let (texture, width, height) = match &self.target {
+            RenderTarget::Offscreen { texture, width, height } => (texture, *width, *height),
+            RenderTarget::Window { .. } => return Err(GpuContextError::NotOffscreenError),
+        };
+
+        let bgra = self.read_texture(texture, width, height, 4)?;
+        Ok(bgra_to_rgba_image(width, height, bgra))
+    }
+
+    /// Copies the first mip level of a texture back from the GPU, returning its texels packed
+    /// tightly row by row. The texture must have `COPY_SRC` usage, and texels `bytes_per_texel`
+    /// bytes wide. This blocks until every command submitted so far has finished executing.
+    pub fn read_texture(
+        &self,
+        texture: &wgpu::Texture,
+        width: u32,
+        height: u32,
+        bytes_per_texel: u32,
+    ) -> Result<Vec<u8>, GpuContextError> {
+        let row_bytes = bytes_per_texel * width;
+        let padded_row_bytes = (row_bytes + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            / COPY_BYTES_PER_ROW_ALIGNMENT * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.create_command_encoder();
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_row_bytes,
+                    rows_per_image: 0,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+        self.submit_command_encoder(encoder);
+
+        // Nothing drives the mapping forward on its own, so wait on the device until it is done.
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).map_err(|e| GpuContextError::BufferMapError(e))?;
+
+        // Strip the padding off of the end of each row.
+        let mut texels = Vec::with_capacity((row_bytes * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_row_bytes as usize) {
+                texels.extend_from_slice(&row[..row_bytes as usize]);
+            }
+        }
+        buffer.unmap();
+
+        Ok(texels)
+    }
+
     //
     // Forwarding functions.
     //
@@ -155,6 +326,10 @@ impl GpuContext {
         self.device.create_render_pipeline(desc)
     }
 
+    pub fn create_buffer(&self, desc: &wgpu::BufferDescriptor) -> wgpu::Buffer {
+        self.device.create_buffer(desc)
+    }
+
     pub fn create_buffer_with_data(&self, data: &[u8], usage: wgpu::BufferUsage) -> wgpu::Buffer {
         self.device.create_buffer_with_data(data, usage)
     }
@@ -169,15 +344,59 @@ impl GpuContext {
         &self.queue
     }
 
-    /// Gets the aspect ratio of the current swap chain.
+    /// Gets the aspect ratio of the current render target.
     // TODO: This feels weird to have here...
     pub fn aspect_ratio(&self) -> f32 {
-        self.swap_chain_desc.width as f32 / self.swap_chain_desc.height as f32
+        let (width, height) = self.size();
+        width as f32 / height as f32
     }
 
-    /// Gets the dimensions of the current swap chain.
+    /// Gets the dimensions of the current render target.
     // TODO: This feels weird to have here...
     pub fn size(&self) -> (u32, u32) {
-        (self.swap_chain_desc.width, self.swap_chain_desc.height)
+        match &self.target {
+            RenderTarget::Window { swap_chain_desc, .. } => (swap_chain_desc.width, swap_chain_desc.height),
+            RenderTarget::Offscreen { width, height, .. } => (*width, *height),
+        }
+    }
+}
+
+/// Converts texels read back from a texture of `FRAME_FORMAT`, which is BGRA, into an image.
+pub fn bgra_to_rgba_image(width: u32, height: u32, mut texels: Vec<u8>) -> image::RgbaImage {
+    for texel in texels.chunks_mut(4) {
+        texel.swap(0, 2);
     }
+    image::RgbaImage::from_raw(width, height, texels).expect("texel buffer has the wrong size")
+}
+
+/// Request the device handle and the command queue handle for the adapter.
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), GpuContextError> {
+    // TODO: These features will eventually need to be parameters.
+    let required_features = wgpu::Features::empty();
+
+    adapter.request_device(&wgpu::DeviceDescriptor {
+        features: adapter.features() & required_features,
+        limits: wgpu::Limits::default(),
+        shader_validation: true,
+    }, None)
+    .await
+    .map_err(|e| GpuContextError::RequestDeviceError(e))
+}
+
+/// Create the texture a headless `GpuContext` renders into. It stands in for a swap chain image, so
+/// it has the same format, but it can also be copied out of.
+fn create_offscreen_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FRAME_FORMAT,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        label: None,
+    })
 }
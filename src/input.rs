@@ -1,10 +1,20 @@
-use winit::event::VirtualKeyCode;
+use std::collections::HashSet;
+
+use winit::event::{ElementState, VirtualKeyCode};
 
 use crate::render_context;
 
 pub struct InputContext {
     x_sensitivity: f32,
     y_sensitivity: f32,
+    /// How fast the camera moves while a movement key is held, in units per second.
+    move_speed: f32,
+    /// How fast the sun turns while a sun key is held, in radians per second.
+    sun_speed: f32,
+    /// How fast the fog thickens or thins while a fog key is held, as a factor per second.
+    fog_rate: f32,
+    /// The keys which are currently held down.
+    held_keys: HashSet<VirtualKeyCode>,
 }
 
 impl InputContext {
@@ -12,34 +22,104 @@ impl InputContext {
         Self {
             x_sensitivity: 500.0,
             y_sensitivity: 500.0,
+            move_speed: 15.0,
+            sun_speed: 0.5,
+            fog_rate: 2.0,
+            held_keys: HashSet::new(),
         }
     }
 
-    pub fn handle_key(&self, render_context: &mut render_context::RenderContext, keycode: VirtualKeyCode) {
+    /// Track a key being pressed or released. Movement keys only take effect in `update`, for as
+    /// long as they are held; other keys act once when they are first pressed, and ignore the
+    /// repeated presses sent while they are held.
+    pub fn handle_key(
+        &mut self,
+        render_context: &mut render_context::RenderContext,
+        keycode: VirtualKeyCode,
+        state: ElementState,
+    ) {
+        if state == ElementState::Released {
+            self.held_keys.remove(&keycode);
+            return;
+        }
+        if !self.held_keys.insert(keycode) {
+            return;
+        }
+
         match keycode {
-            VirtualKeyCode::F | VirtualKeyCode::Up => {
-                render_context.camera_mut().move_forward(0.5);
-            },
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                render_context.camera_mut().move_backward(0.5);
-            },
-            VirtualKeyCode::R | VirtualKeyCode::Left => {
-                render_context.camera_mut().move_left(0.5);
+            // F2 saves a screenshot, and F3 saves one along with the depth buffer.
+            VirtualKeyCode::F2 | VirtualKeyCode::F3 => {
+                match render_context.save_screenshot(keycode == VirtualKeyCode::F3) {
+                    Ok(path) => log::info!("Saved screenshot to {}.", path.display()),
+                    Err(e) => log::error!("Failed to save screenshot: {}", e),
+                }
             },
-            VirtualKeyCode::T | VirtualKeyCode::Right => {
-                render_context.camera_mut().move_right(0.5);
-            },
-            VirtualKeyCode::W => {
-                render_context.camera_mut().move_up(0.5);
-            },
-            VirtualKeyCode::P => {
-                render_context.camera_mut().move_down(0.5);
+            // M cycles through the multisampling levels.
+            VirtualKeyCode::M => {
+                let counts = &crate::render_context::SUPPORTED_SAMPLE_COUNTS;
+                let next = counts
+                    .iter()
+                    .position(|&count| count == render_context.sample_count())
+                    .map_or(counts[0], |i| counts[(i + 1) % counts.len()]);
+                render_context.set_sample_count(next);
             },
             // Ignore other keys.
             _ => {},
         }
     }
 
+    /// Forget every held key. Key releases aren't seen while the window is out of focus, so this
+    /// should be called when focus is lost to keep keys from getting stuck.
+    pub fn release_all_keys(&mut self) {
+        self.held_keys.clear();
+    }
+
+    /// Advance the simulation by `dt` seconds, moving the camera in the direction of the movement
+    /// keys held, turning the sun for each sun key held, and changing the fog for each fog key held.
+    pub fn update(&self, render_context: &mut render_context::RenderContext, dt: f32) {
+        let angle = cgmath::Rad(self.sun_speed * dt);
+        let fog_factor = self.fog_rate.powf(dt);
+        // The movement keys are summed into a single direction, as amounts forward, right, and up,
+        // so that holding two at once doesn't move the camera any faster than holding one.
+        let mut direction = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        for keycode in &self.held_keys {
+            match keycode {
+                VirtualKeyCode::F | VirtualKeyCode::Up => direction.x += 1.0,
+                VirtualKeyCode::S | VirtualKeyCode::Down => direction.x -= 1.0,
+                VirtualKeyCode::T | VirtualKeyCode::Right => direction.y += 1.0,
+                VirtualKeyCode::R | VirtualKeyCode::Left => direction.y -= 1.0,
+                VirtualKeyCode::W => direction.z += 1.0,
+                VirtualKeyCode::P => direction.z -= 1.0,
+                // The brackets raise and lower the sun, and comma and period swing it around the
+                // sky.
+                VirtualKeyCode::RBracket => {
+                    render_context.sun_mut().elevation += angle;
+                },
+                VirtualKeyCode::LBracket => {
+                    render_context.sun_mut().elevation -= angle;
+                },
+                VirtualKeyCode::Period => {
+                    render_context.sun_mut().azimuth += angle;
+                },
+                VirtualKeyCode::Comma => {
+                    render_context.sun_mut().azimuth -= angle;
+                },
+                // Equals thickens the fog, and minus thins it.
+                VirtualKeyCode::Equals => {
+                    render_context.atmosphere_mut().fog_density *= fog_factor;
+                },
+                VirtualKeyCode::Minus => {
+                    render_context.atmosphere_mut().fog_density /= fog_factor;
+                },
+                // Other keys don't move anything.
+                _ => {},
+            }
+        }
+        if direction != cgmath::Vector3::new(0.0, 0.0, 0.0) {
+            render_context.camera_mut().move_along(direction, self.move_speed * dt);
+        }
+    }
+
     pub fn handle_cursor_moved(
         &mut self,
         render_context: &mut render_context::RenderContext,
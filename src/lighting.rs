@@ -0,0 +1,175 @@
+/// The light falling on the world: a sun shining from a single direction, and a flat ambient term
+/// which keeps faces turned away from the sun from going black. Also the atmosphere the light
+/// passes through, which colors the sky and fogs the distance.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Rad, Vector3};
+
+/// Sunlight fades out as the sun sets, over this height of the sun's direction above the horizon
+/// (about three degrees), rather than cutting out all at once.
+const SUNSET_FADE: f32 = 0.05;
+
+pub struct Sun {
+    /// The compass direction of the sun, measured counterclockwise from the x-axis.
+    pub azimuth: Rad<f32>,
+    /// The height of the sun above the horizon. The sun is below the horizon when this is
+    /// negative, and then lights nothing.
+    pub elevation: Rad<f32>,
+    /// The color and intensity of sunlight, in linear RGB.
+    pub color: [f32; 3],
+    /// The color and intensity of the light reaching every face, whichever way it points.
+    pub ambient: [f32; 3],
+}
+
+impl Sun {
+    /// Returns the unit vector pointing from the world toward the sun.
+    pub fn direction(&self) -> Vector3<f32> {
+        let (sin_a, cos_a) = self.azimuth.0.sin_cos();
+        let (sin_e, cos_e) = self.elevation.0.sin_cos();
+        Vector3::new(cos_e * cos_a, cos_e * sin_a, sin_e)
+    }
+
+    /// Returns the view projection matrix of a shadow map which covers everything within
+    /// `corners`, looking along the sun's rays. Anything up to `caster_margin` further toward the
+    /// sun is included too, since it may cast shadows into the covered region.
+    ///
+    /// The region covered is a cube around the bounding sphere of `corners`, so that it doesn't
+    /// change size as the camera turns, and it only moves in whole texels of a `map_size` texel
+    /// shadow map. Otherwise the edges of shadows would crawl as the camera moves.
+    pub fn shadow_matrix(&self, corners: &[Point3<f32>], caster_margin: f32, map_size: u32) -> Matrix4<f32> {
+        let direction = self.direction();
+        // Any up vector will do, so long as it isn't parallel to the sun's rays.
+        let up = if direction.z.abs() > 0.99 { Vector3::unit_y() } else { Vector3::unit_z() };
+        // The view is centered on the origin rather than the camera, so that the texel grid stays
+        // put in the world.
+        let view = Matrix4::look_at_dir(Point3::origin(), -direction, up);
+
+        let center = Point3::centroid(corners);
+        let radius = corners.iter().map(|&c| c.distance(center)).fold(0.0, f32::max).ceil();
+        let texel = 2.0 * radius / map_size as f32;
+        let center = view.transform_point(center);
+        let (x, y) = ((center.x / texel).floor() * texel, (center.y / texel).floor() * texel);
+
+        // The view looks down its negative z-axis, so the near and far distances are negated.
+        let projection = cgmath::ortho(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -center.z - radius - caster_margin,
+            -center.z + radius,
+        );
+        crate::utils::OPENGL_TO_WGPU_MATRIX * projection * view
+    }
+}
+
+impl Default for Sun {
+    /// A warm afternoon sun, low enough that the sides of hills facing it are lit differently from
+    /// the tops.
+    fn default() -> Self {
+        Self {
+            azimuth: Rad(0.6),
+            elevation: Rad(0.9),
+            color: [1.0, 0.95, 0.85],
+            ambient: [0.3, 0.33, 0.4],
+        }
+    }
+}
+
+/// The sky behind the world, and the fog which fades the world into it with distance.
+pub struct Atmosphere {
+    /// The color of the sky straight overhead, in linear RGB.
+    pub zenith_color: [f32; 3],
+    /// The color of the sky at the horizon, in linear RGB. Fog takes on this color too, so that
+    /// distant terrain blends into the sky behind it.
+    pub fog_color: [f32; 3],
+    /// How quickly fog thickens with distance from the camera, per block. Fog hides
+    /// `1 - exp(-(fog_density * distance)^2)` of a fragment's color.
+    pub fog_density: f32,
+}
+
+impl Default for Atmosphere {
+    /// A clear blue sky, with the fog thick enough to hide the edge of the loaded world.
+    fn default() -> Self {
+        Self {
+            zenith_color: [0.15, 0.3, 0.65],
+            fog_color: [0.6, 0.7, 0.8],
+            fog_density: 0.008,
+        }
+    }
+}
+
+/// The sun and the atmosphere as laid out in the shaders' lighting uniform block. Every member is
+/// padded out to a `vec4`, as std140 requires of `vec3`s.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LightingUniforms {
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+    ambient: [f32; 4],
+    zenith_color: [f32; 4],
+    /// The fog color, with the fog density in the fourth component.
+    fog: [f32; 4],
+}
+
+unsafe impl Pod for LightingUniforms {}
+unsafe impl Zeroable for LightingUniforms {}
+
+impl LightingUniforms {
+    pub fn new(sun: &Sun, atmosphere: &Atmosphere) -> Self {
+        let d = sun.direction();
+        // A sun below the horizon would otherwise still light faces pointing down toward it.
+        let fade = (d.z / SUNSET_FADE).clamp(0.0, 1.0);
+        let [r, g, b] = sun.color;
+        let [ar, ag, ab] = sun.ambient;
+        let [zr, zg, zb] = atmosphere.zenith_color;
+        let [fr, fg, fb] = atmosphere.fog_color;
+        LightingUniforms {
+            sun_direction: [d.x, d.y, d.z, 0.0],
+            sun_color: [r * fade, g * fade, b * fade, 0.0],
+            ambient: [ar, ag, ab, 0.0],
+            zenith_color: [zr, zg, zb, 0.0],
+            fog: [fr, fg, fb, atmosphere.fog_density],
+        }
+    }
+}
+
+impl AsRef<[f32]> for LightingUniforms {
+    fn as_ref(&self) -> &[f32] {
+        bytemuck::cast_slice(std::slice::from_ref(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun_at(elevation: f32) -> Sun {
+        Sun {
+            elevation: Rad(elevation),
+            ..Sun::default()
+        }
+    }
+
+    #[test]
+    fn a_sun_above_the_horizon_shines_at_full_strength() {
+        let uniforms = LightingUniforms::new(&sun_at(0.5), &Atmosphere::default());
+        assert_eq!(uniforms.sun_color, [1.0, 0.95, 0.85, 0.0]);
+    }
+
+    #[test]
+    fn a_sun_below_the_horizon_lights_nothing() {
+        for &elevation in &[0.0, -0.01, -0.5, -std::f32::consts::FRAC_PI_2] {
+            let uniforms = LightingUniforms::new(&sun_at(elevation), &Atmosphere::default());
+            assert_eq!(uniforms.sun_color, [0.0; 4], "elevation {}", elevation);
+        }
+    }
+
+    #[test]
+    fn a_setting_sun_fades_out() {
+        let uniforms = LightingUniforms::new(&sun_at(SUNSET_FADE.asin() / 2.0), &Atmosphere::default());
+        let red = uniforms.sun_color[0];
+        assert!(0.4 < red && red < 0.6, "red is {}", red);
+    }
+}
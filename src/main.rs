@@ -2,44 +2,125 @@ use winit::{
     event,
     event::{Event, DeviceEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
+    platform::desktop::EventLoopExtDesktop,
     window::Window
 };
 
+mod allocator;
+mod block;
 mod camera;
+mod chunk;
+mod chunk_worker;
 mod gpu;
 mod input;
+mod lighting;
 mod managed_buffer;
 mod mesh;
 mod render_context;
 mod simplex;
+mod terrain;
+mod texture_array;
 #[allow(dead_code)]
 mod utils;
 mod world_geometry;
 
 use render_context::RenderContext;
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
-    env_logger::init();
+/// The simulation advances in fixed ticks of this length, however often frames are rendered.
+const TICK: std::time::Duration = std::time::Duration::from_nanos(1_000_000_000 / 120);
+/// The most time the simulation will try to catch up on at once. After a long stall, like the window
+/// being dragged, the lost time is dropped rather than simulated in one burst.
+const MAX_CATCH_UP: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// How long `--headless` waits for the world around the camera to load before giving up.
+const HEADLESS_LOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// The number of samples rendered per pixel, unless another is given with `--msaa`.
+const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+/// Options read from the command line.
+struct Options {
+    /// `--headless <path>` renders a single frame into an image at `path`, without needing a
+    /// display.
+    headless: Option<String>,
+    /// `--msaa <samples>` sets the number of samples rendered per pixel. Only the counts in
+    /// `render_context::SUPPORTED_SAMPLE_COUNTS` are accepted.
+    sample_count: u32,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        headless: None,
+        sample_count: DEFAULT_SAMPLE_COUNT,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => {
+                options.headless = Some(args.next().ok_or_else(|| "`--headless` needs a path".to_string())?);
+            },
+            "--msaa" => {
+                options.sample_count = args
+                    .next()
+                    .and_then(|samples| samples.parse().ok())
+                    .ok_or_else(|| "`--msaa` needs a number of samples".to_string())?;
+                if !render_context::SUPPORTED_SAMPLE_COUNTS.contains(&options.sample_count) {
+                    return Err(format!(
+                        "`--msaa {}` is not supported; the number of samples must be one of {:?}. \
+                         2x and 8x multisampling aren't offered, since not every adapter can render them.",
+                        options.sample_count,
+                        render_context::SUPPORTED_SAMPLE_COUNTS,
+                    ));
+                }
+            },
+            _ => return Err(format!("Unrecognized argument `{}`.", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+async fn run(
+    mut event_loop: EventLoop<()>,
+    window: Window,
+    sample_count: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the render context.
-    let mut render_context = RenderContext::create(&window).await.unwrap();
+    let mut render_context = RenderContext::create(&window, sample_count).await?;
     let mut input_context = input::InputContext::new();
 
     // Start focused by default, assuming the application was executed with the intention of using it straight away.
     let mut window_focused: bool = true;
-    let six_ms = std::time::Duration::from_millis(6);
-    // Move our starting time back by the time between frame requests so that we request the first frame right away.
-    let mut prev_frame = std::time::Instant::now() - six_ms;
+    // The time the simulation has been advanced to, and how far behind the clock it is.
+    let mut prev_update = std::time::Instant::now();
+    let mut lag = std::time::Duration::from_secs(0);
+
+    // Any error which stops the event loop is kept here, so that it can be returned once the loop
+    // has exited.
+    let mut result = Ok(());
 
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run_return(|event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::MainEventsCleared => window.request_redraw(),
-            Event::RedrawRequested(_) => {
+            // Run as many simulation ticks as have passed since the last time around, and then render
+            // the result once.
+            Event::MainEventsCleared => {
                 let now = std::time::Instant::now();
-                if now - prev_frame > six_ms {
-                    prev_frame = now;
+                lag = (lag + (now - prev_update)).min(MAX_CATCH_UP);
+                prev_update = now;
+                while lag >= TICK {
+                    input_context.update(&mut render_context, TICK.as_secs_f32());
+                    lag -= TICK;
+                }
+
+                window.request_redraw();
+            },
+            Event::RedrawRequested(_) => {
+                if let Err(e) = render_context.render() {
+                    result = Err(e.into());
+                    *control_flow = ControlFlow::Exit;
                 }
-                render_context.render();
             },
 
             Event::WindowEvent { event: WindowEvent::Resized(size), .. } => render_context.resize(size),
@@ -55,14 +136,20 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                 window.set_cursor_visible(true);
             },
 
-            // Other keypresses go to the input handler.
+            // Other key presses and releases go to the input handler.
             Event::WindowEvent { event: WindowEvent::KeyboardInput { input: event::KeyboardInput {
                 virtual_keycode: Some(keycode),
-                state: event::ElementState::Pressed, ..
-            }, .. }, .. } => input_context.handle_key(&mut render_context, keycode),
+                state, ..
+            }, .. }, .. } => input_context.handle_key(&mut render_context, keycode, state),
 
             // We track if the window has focus so that we can ignore device events when focus is lost.
-            Event::WindowEvent { event: WindowEvent::Focused(b), .. } => window_focused = b,
+            // Keys released while out of focus are never seen, so stop treating any as held.
+            Event::WindowEvent { event: WindowEvent::Focused(b), .. } => {
+                window_focused = b;
+                if !b {
+                    input_context.release_all_keys();
+                }
+            },
 
             Event::WindowEvent { event: WindowEvent::CursorEntered { .. }, .. } => {
                 window.set_cursor_grab(true).unwrap();
@@ -83,11 +170,52 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
             _ => {}
         }
     });
+
+    result
+}
+
+/// Render the world without opening a window, and save the frame to `path` once everything around
+/// the camera has loaded.
+async fn run_headless(path: &str, sample_count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut render_context = RenderContext::create_headless(1280, 720, sample_count).await?;
+
+    // Chunks stream in over many frames, so keep rendering until the last of them has been drawn.
+    // A chunk which never finishes loading would otherwise keep this going forever.
+    let start = std::time::Instant::now();
+    loop {
+        render_context.render()?;
+        if !render_context.is_world_loading() {
+            break;
+        }
+        if start.elapsed() > HEADLESS_LOAD_TIMEOUT {
+            return Err(format!(
+                "The world didn't finish loading within {} seconds.",
+                HEADLESS_LOAD_TIMEOUT.as_secs(),
+            ).into());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(6));
+    }
+
+    render_context.read_pixels()?.save(path)?;
+    Ok(())
 }
 
 fn main() {
-    let event_loop = EventLoop::new();
-    let window = winit::window::Window::new(&event_loop).unwrap();
-    window.set_inner_size(winit::dpi::PhysicalSize::new(1280, 720));
-    futures::executor::block_on(run(event_loop, window));
+    env_logger::init();
+
+    let result = parse_args().map_err(|e| e.into()).and_then(|options| match options.headless {
+        Some(path) => futures::executor::block_on(run_headless(&path, options.sample_count)),
+        None => {
+            let event_loop = EventLoop::new();
+            let window = winit::window::Window::new(&event_loop)
+                .map_err(|e| format!("Creating the window failed: {}", e))?;
+            window.set_inner_size(winit::dpi::PhysicalSize::new(1280, 720));
+            futures::executor::block_on(run(event_loop, window, options.sample_count))
+        },
+    });
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
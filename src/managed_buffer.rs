@@ -1,10 +1,26 @@
 #[allow(dead_code)]
-/// This error type is wholly unused, but I am leaving it her for potential future use.
+/// This error type is never actually produced, but I am leaving it her for potential future use.
+#[derive(Debug)]
 pub enum ManagedBufferError {
     VertexBufError,
     IndexBufError,
 }
 
+impl std::fmt::Display for ManagedBufferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManagedBufferError::VertexBufError => write!(f, "Vertex buffer creation failed!"),
+            ManagedBufferError::IndexBufError => write!(f, "Index buffer creation failed!"),
+        }
+    }
+}
+
+impl std::error::Error for ManagedBufferError {}
+
+/// Copies into a buffer must start and end on a multiple of this many bytes, and buffer sizes must
+/// be a multiple of it too.
+const COPY_ALIGNMENT: usize = 4;
+
 // TODO: Keep track of 'gpu life, since this manages a raw buffer.
 // The `host_data` field was previously a Vec<T> to avoid dealing with this generic type parameter,
 // but sometimes we don't want a heap allocated type (like when dealing when small buffers, like for
@@ -13,12 +29,22 @@ pub enum ManagedBufferError {
 pub struct ManagedBuffer<T, Own: AsRef<[T]>>
   where T: bytemuck::Pod + bytemuck::Zeroable
 {
-    /// This flag is checked on render to see if the buffer needs to be recopied to GPU.
-    dirty: bool,
+    /// The byte ranges of the host data which have changed since the buffer was last flushed to the
+    /// GPU. These are kept sorted, aligned to `COPY_ALIGNMENT`, and merged so that no two ranges
+    /// overlap or touch.
+    dirty_ranges: Vec<std::ops::Range<usize>>,
     /// A managed pointer to the data in CPU memory.
     host_data: Own,
     /// The wgpu *handle* to the underlying raw buffer.
     raw: wgpu::Buffer,
+    /// The usage the raw buffer was created with, kept so that it can be reallocated.
+    usage: wgpu::BufferUsage,
+    /// The number of `T`s the raw buffer has room for. This may be more than the length of the host
+    /// data, so that the buffer doesn't need to be reallocated every time the data grows.
+    capacity: usize,
+    /// Counts the number of times the raw buffer has been reallocated. Anything holding on to the
+    /// raw buffer, like a bind group, must be recreated whenever this changes.
+    generation: u32,
 
     // We don't actually use T in any of the fields, so we need this zero-size field to placate the
     // compiler.
@@ -32,18 +58,41 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
     }
 
     /// Replaces the data in the CPU memory of the ManagedBuffer. This function will not trigger a
-    /// write into the GPU, but it will set the `dirty` flag. This flag should be checked in any
-    /// place where an up-to-date buffer needs to be used, and if the buffer is dirty, it may be
-    /// flushed using the `enqueue_copy_write_command` function to write the data into the GPU.
+    /// write into the GPU, but it will mark the whole buffer dirty. The `dirty` flag should be
+    /// checked in any place where an up-to-date buffer needs to be used, and if the buffer is dirty,
+    /// it may be flushed using the `flush` function to write the data into the GPU.
+    ///
+    /// The new data must be the same length as the old data, or nothing is replaced and `None` is
+    /// returned. Use `resize_data` to replace the data with data of a different length.
     pub fn replace_data(&mut self, new_data: Own) -> Option<Own> {
         if new_data.as_ref().len() == self.host_data.as_ref().len() {
-            self.dirty = true;
+            self.mark_dirty(0..new_data.as_ref().len());
             Some(std::mem::replace(&mut self.host_data, new_data))
         } else {
             None
         }
     }
 
+    fn new_with_data(
+        gpu_context: &crate::gpu::GpuContext,
+        host_data: Own,
+        usage: wgpu::BufferUsage,
+    ) -> ManagedBuffer<T, Own> {
+        let bytes: &[u8] = bytemuck::cast_slice(host_data.as_ref());
+        let raw = gpu_context.create_buffer_with_data(&padded_bytes(bytes, 0..align_up(bytes.len())), usage);
+        let capacity = host_data.as_ref().len();
+        // The buffer is created with the host data already in it, so there is nothing to flush.
+        ManagedBuffer {
+            dirty_ranges: Vec::new(),
+            host_data,
+            raw,
+            usage,
+            capacity,
+            generation: 0,
+            _type: std::marker::PhantomData,
+        }
+    }
+
     /// Create a new vertex buffer with some provided input data. This object manages the data on
     /// both the CPU and the GPU. This buffer is `COPY_DST`, so it can be written to. If the desired
     /// buffer is immutable, this is not the function to use.
@@ -51,16 +100,11 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
-            bytemuck::cast_slice(host_data.as_ref()),
-            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-        );
-        Ok(ManagedBuffer {
-            dirty: true,
+        Ok(Self::new_with_data(
+            gpu_context,
             host_data,
-            raw,
-            _type: std::marker::PhantomData,
-        })
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        ))
     }
 
     /// Create a new index buffer with some provided input data. This object manages the data on
@@ -70,16 +114,11 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
-            bytemuck::cast_slice(host_data.as_ref()),
-            wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
-        );
-        Ok(ManagedBuffer {
-            dirty: true,
+        Ok(Self::new_with_data(
+            gpu_context,
             host_data,
-            raw,
-            _type: std::marker::PhantomData,
-        })
+            wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
+        ))
     }
 
     /// Create a new uniform buffer with some provided input data. This object manages the data on
@@ -89,16 +128,65 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
-            bytemuck::cast_slice(host_data.as_ref()),
-            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        );
-        Ok(ManagedBuffer {
-            dirty: true,
+        Ok(Self::new_with_data(
+            gpu_context,
             host_data,
-            raw,
-            _type: std::marker::PhantomData,
-        })
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        ))
+    }
+
+    /// Replaces the data in the CPU memory of the ManagedBuffer with data of any length, marking the
+    /// whole buffer dirty. If the new data doesn't fit in the raw buffer, the raw buffer is
+    /// reallocated with room to spare, so that a buffer which keeps growing is only reallocated a
+    /// handful of times. The raw buffer is never shrunk.
+    ///
+    /// Returns true if the raw buffer was reallocated. In that case the old raw buffer is freed, and
+    /// anything referencing it must be recreated; see `generation`.
+    pub fn resize_data(&mut self, gpu_context: &crate::gpu::GpuContext, new_data: Own) -> bool {
+        let len = new_data.as_ref().len();
+        self.host_data = new_data;
+
+        let reallocated = len > self.capacity;
+        if reallocated {
+            self.capacity = len.max(self.capacity * 2);
+            let size = align_up(self.capacity * self.t_size());
+            self.raw = gpu_context.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: size as wgpu::BufferAddress,
+                usage: self.usage,
+                mapped_at_creation: false,
+            });
+            self.generation = self.generation.wrapping_add(1);
+        }
+
+        // Everything written into the old raw buffer is stale, or lost along with the buffer.
+        self.dirty_ranges.clear();
+        self.mark_dirty(0..len);
+
+        reallocated
+    }
+
+    /// Overwrites part of the host data, starting `offset` `T`s into the buffer, and marks just that
+    /// range dirty. Unlike `replace_data`, the rest of the buffer is not copied again on the next
+    /// flush.
+    ///
+    /// N.B. This function will panic if the range does not lie within the buffer.
+    pub fn write_slice(&mut self, offset: usize, data: &[T])
+        where Own: AsMut<[T]>
+    {
+        self.host_data.as_mut()[offset..offset + data.len()].copy_from_slice(data);
+        self.mark_dirty(offset..offset + data.len());
+    }
+
+    /// Marks a range of the host data, measured in `T`s, as needing to be copied to the GPU on the
+    /// next flush. The range is merged with any dirty ranges it overlaps or touches.
+    pub fn mark_dirty(&mut self, range: std::ops::Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let bytes = aligned_byte_range(range, self.t_size());
+        merge_range(&mut self.dirty_ranges, bytes);
     }
 
     /// Returns a wgpu::BufferSlice for portion of the buffer specified by the bounds.
@@ -117,36 +205,155 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         self.host_data.as_ref().len()
     }
 
+    /// Returns the number of times the raw buffer has been reallocated. Anything referencing the raw
+    /// buffer, like a bind group, should remember the generation it was created with, and be
+    /// recreated whenever the generation changes.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
     /// Returns true if the buffer is dirty and needs to be flushed to GPU.
     pub fn dirty(&self) -> bool {
-        self.dirty
+        !self.dirty_ranges.is_empty()
     }
 
-    /// Enqueues a command onto the encoder to copy the buffer from CPU to GPU. This will not issue
-    /// a write unless the buffer is dirty, and so can safely be called in a render loop.
+    /// Writes every dirty range of the buffer from CPU to GPU. This will not issue any writes unless
+    /// the buffer is dirty, and so can safely be called in a render loop.
     ///
-    /// Calling this function will reset the dirty flag. Be sure that you finish the command encoder
-    /// and submit it on a queue.
-    // TODO: Should this functionality actually be provided by `wgpu::queue::write_buffer`, which
-    // may be able to avoid a memcpy?
-    pub fn enqueue_copy_command(
-        &mut self,
-        gpu_context: &crate::gpu::GpuContext,
-        encoder: &mut wgpu::CommandEncoder,
-    ) {
-        if !self.dirty { return }
-
-        let stage_buffer = gpu_context.create_buffer_with_data(
-            bytemuck::cast_slice(self.host_data.as_ref()),
-            wgpu::BufferUsage::COPY_SRC
-        );
-
-        encoder.copy_buffer_to_buffer(
-            &stage_buffer, 0, &self.raw, 0, (self.host_data.as_ref().len() * self.t_size()) as u64
-        );
-
-        // We are setting the dirty flag to false here trusting that the caller will actually
-        // finish the command encoder and submit it on the queue!
-        self.dirty = false;
+    /// The writes are scheduled on the queue, and take effect before the next command buffer
+    /// submitted to it. Calling this function will clear the dirty ranges.
+    pub fn flush(&mut self, gpu_context: &crate::gpu::GpuContext) {
+        let bytes: &[u8] = bytemuck::cast_slice(self.host_data.as_ref());
+        for range in self.dirty_ranges.drain(..) {
+            let start = range.start as wgpu::BufferAddress;
+            gpu_context.queue().write_buffer(&self.raw, start, &padded_bytes(bytes, range));
+        }
+    }
+}
+
+impl<T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer<T, Vec<T>> {
+    /// Resizes the host data to `len` `T`s, keeping its contents and filling any new space with
+    /// zeroes. The raw buffer is reallocated like in `resize_data` if the data no longer fits, in
+    /// which case the whole buffer is marked dirty; otherwise only the new space is.
+    ///
+    /// Returns true if the raw buffer was reallocated.
+    pub fn resize(&mut self, gpu_context: &crate::gpu::GpuContext, len: usize) -> bool {
+        let old_len = self.host_data.len();
+        if len > self.capacity {
+            let mut host_data = std::mem::replace(&mut self.host_data, Vec::new());
+            host_data.resize(len, T::zeroed());
+            return self.resize_data(gpu_context, host_data);
+        }
+
+        self.host_data.resize(len, T::zeroed());
+        self.mark_dirty(old_len..len);
+        false
+    }
+}
+
+/// Rounds `n` up to a multiple of `COPY_ALIGNMENT`.
+fn align_up(n: usize) -> usize {
+    (n + COPY_ALIGNMENT - 1) / COPY_ALIGNMENT * COPY_ALIGNMENT
+}
+
+/// Converts a range of `T`s, each `t_size` bytes, into a range of bytes. Copies have to be aligned,
+/// so the range is widened out to the nearest aligned bytes. At the end of the data, this may run
+/// up to `COPY_ALIGNMENT - 1` bytes past it, into the padding at the end of the raw buffer; see
+/// `padded_bytes`.
+fn aligned_byte_range(range: std::ops::Range<usize>, t_size: usize) -> std::ops::Range<usize> {
+    range.start * t_size / COPY_ALIGNMENT * COPY_ALIGNMENT..align_up(range.end * t_size)
+}
+
+/// Returns the given range of `bytes`. Any part of the range past the end of `bytes` is filled with
+/// zeroes, so that an aligned range can always be copied in full.
+fn padded_bytes(bytes: &[u8], range: std::ops::Range<usize>) -> std::borrow::Cow<'_, [u8]> {
+    if range.end <= bytes.len() {
+        return std::borrow::Cow::Borrowed(&bytes[range]);
+    }
+
+    let mut padded = bytes[range.start.min(bytes.len())..].to_vec();
+    padded.resize(range.end - range.start, 0);
+    std::borrow::Cow::Owned(padded)
+}
+
+/// Inserts `new` into a sorted list of disjoint ranges, merging it with every range it overlaps or
+/// touches so that the list stays sorted and disjoint.
+fn merge_range(ranges: &mut Vec<std::ops::Range<usize>>, new: std::ops::Range<usize>) {
+    // Find the first range which ends at or after the start of the new range, and the first which
+    // starts after its end. Everything in between is merged into the new range.
+    let first = ranges.iter().position(|r| r.end >= new.start).unwrap_or(ranges.len());
+    let last = ranges[first..].iter().position(|r| r.start > new.end).map_or(ranges.len(), |i| first + i);
+
+    let merged = if first < last {
+        new.start.min(ranges[first].start)..new.end.max(ranges[last - 1].end)
+    } else {
+        new
+    };
+    ranges.splice(first..last, std::iter::once(merged));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Merge each range in turn into an empty list, returning the list.
+    fn merged(ranges: &[std::ops::Range<usize>]) -> Vec<std::ops::Range<usize>> {
+        let mut dirty = Vec::new();
+        for range in ranges {
+            merge_range(&mut dirty, range.clone());
+        }
+        dirty
+    }
+
+    #[test]
+    fn disjoint_ranges_stay_separate_and_sorted() {
+        assert_eq!(merged(&[8..12, 0..4, 20..24]), vec![0..4, 8..12, 20..24]);
+    }
+
+    #[test]
+    fn overlapping_ranges_merge() {
+        assert_eq!(merged(&[0..8, 4..12]), vec![0..12]);
+        assert_eq!(merged(&[4..12, 0..8]), vec![0..12]);
+        assert_eq!(merged(&[0..12, 4..8]), vec![0..12]);
+    }
+
+    #[test]
+    fn touching_ranges_merge() {
+        assert_eq!(merged(&[0..4, 4..8]), vec![0..8]);
+        assert_eq!(merged(&[4..8, 0..4]), vec![0..8]);
+        assert_eq!(merged(&[0..4, 8..12, 4..8]), vec![0..12]);
+    }
+
+    #[test]
+    fn a_range_swallows_every_range_it_covers() {
+        assert_eq!(merged(&[0..4, 8..12, 16..20, 24..28, 40..44, 2..26]), vec![0..28, 40..44]);
+        assert_eq!(merged(&[8..12, 16..20, 0..100]), vec![0..100]);
+    }
+
+    #[test]
+    fn byte_ranges_widen_to_the_copy_alignment() {
+        // Already aligned.
+        assert_eq!(aligned_byte_range(1..3, 4), 4..12);
+        // Two-byte elements are widened out to whole words on both ends.
+        assert_eq!(aligned_byte_range(1..2, 2), 0..4);
+        assert_eq!(aligned_byte_range(3..6, 2), 4..12);
+        // Widening at the end of the data runs into the padding after it.
+        assert_eq!(aligned_byte_range(5..7, 1), 4..8);
+        assert_eq!(aligned_byte_range(0..3, 2), 0..8);
+        // Widened neighbors overlap, and merge into one copy.
+        let mut dirty = Vec::new();
+        merge_range(&mut dirty, aligned_byte_range(0..1, 2));
+        merge_range(&mut dirty, aligned_byte_range(3..4, 2));
+        assert_eq!(dirty, vec![0..8]);
+    }
+
+    #[test]
+    fn copies_past_the_end_of_the_data_are_padded() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7];
+        assert_eq!(&*padded_bytes(&bytes, 0..4), &[1, 2, 3, 4]);
+        assert_eq!(&*padded_bytes(&bytes, 4..8), &[5, 6, 7, 0]);
+        assert_eq!(&*padded_bytes(&bytes, 0..8), &[1, 2, 3, 4, 5, 6, 7, 0]);
+        assert_eq!(&*padded_bytes(&[], 0..align_up(0)), &[] as &[u8]);
+        assert_eq!(&*padded_bytes(&[9, 9], 0..align_up(2)), &[9, 9, 0, 0]);
     }
 }
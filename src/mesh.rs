@@ -6,6 +6,7 @@
 /// In fact, we probably want a `Mesh` type to further abstract this.
 
 use cgmath::prelude::*;
+use crate::block::{BlockId, BlockRegistry};
 use crate::utils::{IVertex, Vertex};
 use cgmath::{Point3, Rad, Vector3, Quaternion};
 
@@ -22,6 +23,103 @@ bitflags! {
     }
 }
 
+impl CuboidFaces {
+    /// Every individual face, in the order they are emitted by `add_cuboid_faces`.
+    pub const ALL: [CuboidFaces; 6] = [
+        CuboidFaces::TOP,
+        CuboidFaces::BOTTOM,
+        CuboidFaces::FRONT,
+        CuboidFaces::BACK,
+        CuboidFaces::LEFT,
+        CuboidFaces::RIGHT,
+    ];
+
+    /// Returns the position of a single face in `CuboidFaces::ALL`.
+    pub fn index(self) -> usize {
+        CuboidFaces::ALL.iter().position(|&f| f == self).expect("bad faces")
+    }
+
+    /// Returns the outward facing unit normal of a single face.
+    ///
+    /// Up is assumed to be the z-axis.
+    pub fn normal(self) -> Vector3<i32> {
+        match self {
+            CuboidFaces::TOP => Vector3::new(0, 0, 1),
+            CuboidFaces::BOTTOM => Vector3::new(0, 0, -1),
+            CuboidFaces::FRONT => Vector3::new(0, 1, 0),
+            CuboidFaces::BACK => Vector3::new(0, -1, 0),
+            CuboidFaces::LEFT => Vector3::new(-1, 0, 0),
+            CuboidFaces::RIGHT => Vector3::new(1, 0, 0),
+            _ => panic!("bad faces"),
+        }
+    }
+
+    /// Returns the corners of a single face on a unit cube, in the order vertices are emitted for
+    /// them. Going from one corner to the next winds counterclockwise around the face, seen from
+    /// outside the cube.
+    ///
+    /// Up is assumed to be the z-axis.
+    pub fn corners(self) -> [Vector3<i32>; 4] {
+        match self {
+            CuboidFaces::TOP => [
+                Vector3::new(1, 1, 1),
+                Vector3::new(0, 1, 1),
+                Vector3::new(0, 0, 1),
+                Vector3::new(1, 0, 1),
+            ],
+            CuboidFaces::BOTTOM => [
+                Vector3::new(1, 0, 0),
+                Vector3::new(0, 0, 0),
+                Vector3::new(0, 1, 0),
+                Vector3::new(1, 1, 0),
+            ],
+            CuboidFaces::FRONT => [
+                Vector3::new(1, 1, 0),
+                Vector3::new(0, 1, 0),
+                Vector3::new(0, 1, 1),
+                Vector3::new(1, 1, 1),
+            ],
+            CuboidFaces::BACK => [
+                Vector3::new(0, 0, 0),
+                Vector3::new(1, 0, 0),
+                Vector3::new(1, 0, 1),
+                Vector3::new(0, 0, 1),
+            ],
+            CuboidFaces::LEFT => [
+                Vector3::new(0, 1, 0),
+                Vector3::new(0, 0, 0),
+                Vector3::new(0, 0, 1),
+                Vector3::new(0, 1, 1),
+            ],
+            CuboidFaces::RIGHT => [
+                Vector3::new(1, 0, 0),
+                Vector3::new(1, 1, 0),
+                Vector3::new(1, 1, 1),
+                Vector3::new(1, 0, 1),
+            ],
+            _ => panic!("bad faces"),
+        }
+    }
+}
+
+/// The ambient occlusion of a vertex, from 0 (darkest) to 3 (unoccluded), given which of the
+/// blocks around it are opaque. The blocks are the two sharing an edge with the vertex's face, and
+/// the one sharing only its corner, all in the layer of blocks in front of the face. When both
+/// sides are filled the corner is hidden too, however it is filled.
+pub fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - (side1 as u8 + side2 as u8 + corner as u8)
+    }
+}
+
+/// The ambient occlusion of the four corners of a face which nothing is occluding.
+pub const NO_OCCLUSION: [u8; 4] = [3; 4];
+
+/// The light level given to a vertex for each of its ambient occlusion values.
+const AO_LIGHT: [u8; 4] = [90, 150, 205, 255];
+
 // TODO: This will need to be generic over vertex type, index type, and index offset type
 // eventually.
 pub struct MeshAccumulator {
@@ -171,20 +269,101 @@ impl MeshAccumulator {
     }
 }
 
-pub struct IMeshAccumulator {
-    vertex_accum: Vec<IVertex>,
-    index_accum: Vec<u16>,
-    index_offset: u16,
+/// An integer type which can be used for the indices of a mesh.
+pub trait MeshIndex: bytemuck::Pod + bytemuck::Zeroable {
+    /// The format a render pipeline must use to read indices of this type.
+    const FORMAT: wgpu::IndexFormat;
+    /// The number of vertices which indices of this type can address.
+    const MAX_VERTICES: usize;
+
+    /// Converts a vertex index into this type. The caller must ensure that `i` is less than
+    /// `MAX_VERTICES`.
+    fn from_usize(i: usize) -> Self;
+}
+
+impl MeshIndex for u16 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+    const MAX_VERTICES: usize = 1 << 16;
+
+    #[inline(always)]
+    fn from_usize(i: usize) -> Self {
+        i as u16
+    }
+}
+
+impl MeshIndex for u32 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+    const MAX_VERTICES: usize = u32::MAX as usize;
+
+    #[inline(always)]
+    fn from_usize(i: usize) -> Self {
+        i as u32
+    }
+}
+
+/// A vertex type which voxel meshes can be built from.
+pub trait MeshVertex: bytemuck::Pod + bytemuck::Zeroable {
+    /// The layout of a vertex, as read by `VERTEX_SHADER`.
+    const ATTRIBUTES: &'static [wgpu::VertexAttributeDescriptor];
+    /// SPIR-V for a vertex shader which decodes this format.
+    const VERTEX_SHADER: &'static [u8];
+
+    /// Create a vertex at the given position within the chunk. `b_pos` is the block the vertex's
+    /// face belongs to, `corner` numbers the corners of the face in the order they are emitted, and
+    /// `tc` counts blocks across the face.
+    fn new(
+        v_pos: Vector3<i32>,
+        b_pos: Point3<i32>,
+        tc: [f32; 2],
+        face: CuboidFaces,
+        corner: u8,
+        layer: u8,
+        light: u8,
+    ) -> Self;
+}
+
+/// Returned by `IMeshAccumulator::report` when the mesh had more vertices than its index type can
+/// address.
+#[derive(Debug)]
+pub struct MeshIndexOverflow {
+    /// The number of vertices in the mesh, including those which were dropped.
+    pub vertex_count: usize,
+    pub max_vertices: usize,
+}
+
+impl std::fmt::Display for MeshIndexOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mesh has {} vertices, but its index type can only address {}.",
+            self.vertex_count, self.max_vertices,
+        )
+    }
+}
+
+impl std::error::Error for MeshIndexOverflow {}
+
+/// Accumulates voxel faces into a mesh, with indices of type `I` and vertices of type `V`.
+///
+/// If more vertices are added than `I` can address, the faces which don't fit are dropped and
+/// `report` returns an error, rather than letting the indices wrap around.
+pub struct IMeshAccumulator<I: MeshIndex = u32, V: MeshVertex = IVertex> {
+    vertex_accum: Vec<V>,
+    index_accum: Vec<I>,
+    index_offset: usize,
+    /// The number of vertices dropped because they could not be addressed by `I`.
+    overflow: usize,
 }
 
 #[allow(dead_code)]
-impl IMeshAccumulator {
+impl<I: MeshIndex, V: MeshVertex> IMeshAccumulator<I, V> {
     /// Create a new `IMeshAccumulator`.
     pub fn new() -> Self {
         Self {
             vertex_accum: Vec::new(),
             index_accum: Vec::new(),
             index_offset: 0,
+            overflow: 0,
         }
     }
 
@@ -196,100 +375,192 @@ impl IMeshAccumulator {
             vertex_accum: Vec::with_capacity(vertex_capacity),
             index_accum: Vec::with_capacity(index_capacity),
             index_offset: 0,
+            overflow: 0,
         }
     }
 
     // TODO: Return new `Mesh` type.
-    /// Consumes the `MeshAccumulator` and returns the vertex and index buffers.
-    pub fn report(self) -> (Vec<IVertex>, Vec<u16>) {
-        (self.vertex_accum, self.index_accum)
+    /// Consumes the `MeshAccumulator` and returns the vertex and index buffers. If any faces were
+    /// dropped because their vertices could not be addressed by `I`, an error is returned instead.
+    pub fn report(self) -> Result<(Vec<V>, Vec<I>), MeshIndexOverflow> {
+        if self.overflow > 0 {
+            return Err(MeshIndexOverflow {
+                vertex_count: self.index_offset + self.overflow,
+                max_vertices: I::MAX_VERTICES,
+            });
+        }
+        Ok((self.vertex_accum, self.index_accum))
     }
 
-    /// Add an isolated cuboid to the mesh. The parameter names are self-describing.
+    /// Add an isolated cuboid to the mesh. The parameter names are self-describing. Each face is
+    /// textured according to the definition of `block` in `registry`.
     ///
     /// Up is assumed to be the z-axis.
     pub fn add_cuboid_faces(
         &mut self,
         pos: Point3<i32>,
         faces: CuboidFaces,
+        block: BlockId,
+        registry: &BlockRegistry,
     ) {
         if faces.contains(CuboidFaces::TOP) {
-            self.add_face(pos, CuboidFaces::TOP);
+            self.add_face(pos, CuboidFaces::TOP, block, registry);
         }
         if faces.contains(CuboidFaces::BOTTOM) {
-            self.add_face(pos, CuboidFaces::BOTTOM);
+            self.add_face(pos, CuboidFaces::BOTTOM, block, registry);
         }
         if faces.contains(CuboidFaces::FRONT) {
-            self.add_face(pos, CuboidFaces::FRONT);
+            self.add_face(pos, CuboidFaces::FRONT, block, registry);
         }
         if faces.contains(CuboidFaces::BACK) {
-            self.add_face(pos, CuboidFaces::BACK);
+            self.add_face(pos, CuboidFaces::BACK, block, registry);
         }
         if faces.contains(CuboidFaces::LEFT) {
-            self.add_face(pos, CuboidFaces::LEFT);
+            self.add_face(pos, CuboidFaces::LEFT, block, registry);
         }
         if faces.contains(CuboidFaces::RIGHT) {
-            self.add_face(pos, CuboidFaces::RIGHT);
+            self.add_face(pos, CuboidFaces::RIGHT, block, registry);
         }
     }
 
-    /// Add an isolated quad to the mesh. The parameter names are self-describing.
+    /// Add an isolated quad to the mesh. The parameter names are self-describing. The quad is
+    /// textured with the texture array layer that `registry` assigns to this face of `block`, and
+    /// is not occluded.
     ///
     /// Up is assumed to be the z-axis.
     pub fn add_face(
         &mut self,
         pos: Point3<i32>,
         face: CuboidFaces,
+        block: BlockId,
+        registry: &BlockRegistry,
+    ) {
+        self.add_box_face(pos, Vector3::new(1, 1, 1), face, block, registry, NO_OCCLUSION);
+    }
+
+    /// Add a single face of a box of blocks to the mesh. The box has its minimum corner at `pos`
+    /// and is `extent` blocks in size; faces merged by the greedy mesher are one block thick along
+    /// their normal. The texture for this face of `block` is repeated once per block across the
+    /// face. `ao` is the ambient occlusion of each corner, in the order of `CuboidFaces::corners`;
+    /// see `vertex_ao`.
+    ///
+    /// Up is assumed to be the z-axis.
+    pub fn add_box_face(
+        &mut self,
+        pos: Point3<i32>,
+        extent: Vector3<i32>,
+        face: CuboidFaces,
+        block: BlockId,
+        registry: &BlockRegistry,
+        ao: [u8; 4],
     ) {
+        if self.index_offset + 4 > I::MAX_VERTICES {
+            self.overflow += 4;
+            return;
+        }
         let i = self.index_offset;
 
-        let [v1, v2, v3, v4] = match face {
-            CuboidFaces::TOP => [
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
-                Vector3::new(pos.x  , pos.y+1, pos.z+1),
-                Vector3::new(pos.x  , pos.y  , pos.z+1),
-                Vector3::new(pos.x+1, pos.y  , pos.z+1),
-            ],
-            CuboidFaces::BOTTOM => [
-                Vector3::new(pos.x+1, pos.y  , pos.z),
-                Vector3::new(pos.x  , pos.y  , pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
-            ],
-            CuboidFaces::FRONT => [
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z+1),
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
-            ],
-            CuboidFaces::BACK => [
-                Vector3::new(pos.x,   pos.y, pos.z),
-                Vector3::new(pos.x+1, pos.y, pos.z),
-                Vector3::new(pos.x+1, pos.y, pos.z+1),
-                Vector3::new(pos.x,   pos.y, pos.z+1),
-            ],
-            CuboidFaces::LEFT => [
-                Vector3::new(pos.x, pos.y+1, pos.z),
-                Vector3::new(pos.x, pos.y,   pos.z),
-                Vector3::new(pos.x, pos.y,   pos.z+1),
-                Vector3::new(pos.x, pos.y+1, pos.z+1),
-            ],
-            CuboidFaces::RIGHT => [
-                Vector3::new(pos.x+1, pos.y,   pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
-                Vector3::new(pos.x+1, pos.y,   pos.z+1),
-            ],
-            _ => panic!("bad faces"),
-        };
+        let [v1, v2, v3, v4] = face.corners();
+
+        // Stretch the unit face out to the size of the box. The texture repeats once per block, so
+        // the texture coordinates span the length of the face's edges.
+        let u = (v2 - v1).mul_element_wise(extent).sum().abs() as f32;
+        let v = (v3 - v2).mul_element_wise(extent).sum().abs() as f32;
+        let corner = |c: Vector3<i32>| pos.to_vec() + c.mul_element_wise(extent);
+
+        let layer = registry.get(block).layer(face) as u8;
 
         self.vertex_accum.extend(&[
-            IVertex::new(v1, pos, [0.0, 0.0], face.bits()),
-            IVertex::new(v2, pos, [1.0, 0.0], face.bits()),
-            IVertex::new(v3, pos, [1.0, 1.0], face.bits()),
-            IVertex::new(v4, pos, [0.0, 1.0], face.bits()),
+            V::new(corner(v1), pos, [0.0, 0.0], face, 0, layer, AO_LIGHT[ao[0] as usize]),
+            V::new(corner(v2), pos, [u, 0.0], face, 1, layer, AO_LIGHT[ao[1] as usize]),
+            V::new(corner(v3), pos, [u, v], face, 2, layer, AO_LIGHT[ao[2] as usize]),
+            V::new(corner(v4), pos, [0.0, v], face, 3, layer, AO_LIGHT[ao[3] as usize]),
         ]);
-        self.index_accum.extend(&[0+i, 1+i, 2+i, 2+i, 3+i, 0+i]);
+        // Light is interpolated across each triangle separately, so the quad is split along the
+        // diagonal joining its brighter pair of corners. Splitting along the other diagonal would
+        // smear the darkness of one corner across the whole quad, in a direction which depends on
+        // how the quad happened to be wound.
+        let indices = if ao[0] + ao[2] >= ao[1] + ao[3] {
+            [0+i, 1+i, 2+i, 2+i, 3+i, 0+i]
+        } else {
+            [1+i, 2+i, 3+i, 3+i, 0+i, 1+i]
+        };
+        self.index_accum.extend(indices.iter().map(|&i| I::from_usize(i)));
         self.index_offset += 4;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block;
+
+    /// Accumulate `faces` separate faces, each on a block of its own.
+    fn accumulate<I: MeshIndex>(faces: usize, registry: &BlockRegistry) -> IMeshAccumulator<I> {
+        let mut accum = IMeshAccumulator::<I>::new();
+        for i in 0..faces as i32 {
+            accum.add_face(Point3::new(i % 256, i / 256, 0), CuboidFaces::TOP, block::SNOW, registry);
+        }
+        accum
+    }
+
+    #[test]
+    fn vertex_ao_counts_the_occluding_blocks() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(true, false, false), 2);
+        assert_eq!(vertex_ao(false, true, false), 2);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(false, true, true), 1);
+        // Both sides hide the corner, however it is filled.
+        assert_eq!(vertex_ao(true, true, false), 0);
+        assert_eq!(vertex_ao(true, true, true), 0);
+    }
+
+    /// Returns the indices of a single face added with the given ambient occlusion.
+    fn face_indices(ao: [u8; 4], registry: &BlockRegistry) -> Vec<u32> {
+        let mut accum = IMeshAccumulator::<u32>::new();
+        accum.add_box_face(Point3::new(0, 0, 0), Vector3::new(1, 1, 1), CuboidFaces::TOP, block::SNOW, registry, ao);
+        accum.report().map(|(_, indices)| indices).unwrap_or_default()
+    }
+
+    #[test]
+    fn quads_split_along_their_brighter_diagonal() {
+        let registry = BlockRegistry::for_tests();
+        // Corners 0 and 2 are brighter, or the diagonals are equal, so the split runs from 0 to 2.
+        assert_eq!(face_indices(NO_OCCLUSION, &registry), vec![0, 1, 2, 2, 3, 0]);
+        assert_eq!(face_indices([3, 0, 3, 0], &registry), vec![0, 1, 2, 2, 3, 0]);
+        assert_eq!(face_indices([2, 3, 2, 1], &registry), vec![0, 1, 2, 2, 3, 0]);
+        // Corners 1 and 3 are brighter, so the split runs from 1 to 3.
+        assert_eq!(face_indices([0, 3, 0, 3], &registry), vec![1, 2, 3, 3, 0, 1]);
+        assert_eq!(face_indices([0, 3, 3, 3], &registry), vec![1, 2, 3, 3, 0, 1]);
+    }
+
+    #[test]
+    fn u16_indices_address_every_vertex_up_to_the_limit() {
+        let registry = BlockRegistry::for_tests();
+        let (vertices, indices) = accumulate::<u16>(16_384, &registry).report().unwrap();
+        assert_eq!(vertices.len(), 1 << 16);
+        assert_eq!(indices.len(), 6 * 16_384);
+        assert_eq!(indices.iter().copied().max(), Some(u16::MAX));
+    }
+
+    #[test]
+    fn u16_indices_overflow_past_the_limit() {
+        let registry = BlockRegistry::for_tests();
+        let err = match accumulate::<u16>(16_385, &registry).report() {
+            Ok(_) => panic!("a mesh of 16,385 faces fit into u16 indices"),
+            Err(err) => err,
+        };
+        assert_eq!(err.vertex_count, 4 * 16_385);
+        assert_eq!(err.max_vertices, 1 << 16);
+    }
+
+    #[test]
+    fn u32_indices_address_past_the_u16_limit() {
+        let registry = BlockRegistry::for_tests();
+        let (vertices, indices) = accumulate::<u32>(16_385, &registry).report().unwrap();
+        assert_eq!(vertices.len(), 4 * 16_385);
+        assert_eq!(indices.iter().copied().max(), Some(4 * 16_385 - 1));
+    }
+}
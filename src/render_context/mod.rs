@@ -1,10 +1,31 @@
 use winit::window::Window;
 
 use crate::camera;
+use crate::gpu::GpuContextError;
+use crate::lighting;
+use crate::managed_buffer::ManagedBufferError;
+use crate::texture_array::TextureError;
 use crate::utils;
 
 #[cfg(debug_assertions)]
 mod debug_pass;
+mod screenshot;
+mod shadow;
+mod sky;
+
+/// The horizontal and vertical radii, in chunks, of the region of world geometry kept loaded around
+/// the camera.
+const DEFAULT_VIEW_RADIUS: i32 = 15;
+const DEFAULT_VIEW_RADIUS_Z: i32 = 3;
+
+/// Every PNG in this directory is loaded as a layer of the block texture array.
+const TEXTURE_DIR: &str = "textures";
+
+/// The numbers of samples per pixel which multisampling may be set to. wgpu has no way to ask an
+/// adapter which counts it can render `gpu::FRAME_FORMAT` and the depth buffer with, and creating a
+/// target with a count it can't is a fatal error, so only the counts which every adapter must
+/// support are allowed.
+pub const SUPPORTED_SAMPLE_COUNTS: [u32; 2] = [1, 4];
 
 /// A `RenderContext` stores any state that is required for rendering a frame. This may include:
 ///
@@ -34,112 +55,168 @@ pub struct RenderContext {
 
     depth_buffer: wgpu::Texture,
     depth_buffer_view: wgpu::TextureView,
-    depth_buffer_sampler: wgpu::Sampler,
+
+    /// The number of samples rendered per pixel. When this is more than one, the world is drawn
+    /// into `multisampled_framebuffer` and resolved into the frame.
+    sample_count: u32,
+    multisampled_framebuffer: Option<(wgpu::Texture, wgpu::TextureView)>,
 
     camera: camera::Camera,
     // For now, this only stores the camera's matrix.
     uniform_buf: crate::managed_buffer::ManagedBuffer<f32, utils::Matrix4>,
+    view_buf: crate::managed_buffer::ManagedBuffer<f32, utils::ViewUniforms>,
+
+    sun: lighting::Sun,
+    atmosphere: lighting::Atmosphere,
+    lighting_buf: crate::managed_buffer::ManagedBuffer<f32, lighting::LightingUniforms>,
+    shadow_map: shadow::ShadowMap,
+    sky: sky::Sky,
 
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    /// The generation of the transforms buffer which `bind_group` was created with.
+    bind_group_transforms_generation: u32,
 
     pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
 
     camera_dirty: bool,
+    lighting_dirty: bool,
+    /// Set while the window is minimized. There is nothing to render into, so frames are skipped.
+    minimized: bool,
+}
+
+/// Everything which can go wrong while creating a `RenderContext` or rendering with it.
+#[derive(Debug)]
+pub enum RenderError {
+    GpuError(GpuContextError),
+    BufferError(ManagedBufferError),
+    TextureError(TextureError),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::GpuError(err) => write!(f, "{}", err),
+            RenderError::BufferError(err) => write!(f, "{}", err),
+            RenderError::TextureError(err) => write!(f, "Loading the block textures failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<GpuContextError> for RenderError {
+    fn from(err: GpuContextError) -> Self {
+        RenderError::GpuError(err)
+    }
+}
+
+impl From<ManagedBufferError> for RenderError {
+    fn from(err: ManagedBufferError) -> Self {
+        RenderError::BufferError(err)
+    }
+}
+
+impl From<TextureError> for RenderError {
+    fn from(err: TextureError) -> Self {
+        RenderError::TextureError(err)
+    }
 }
 
 impl RenderContext {
-    // TODO: `Option` -> `Result`.
-    pub async fn create(window: &Window) -> Option<RenderContext> {
-        let gpu_context = crate::gpu::GpuContext::create(window).await.unwrap();
+    /// Create a `RenderContext` which renders into the window, rendering `sample_count` samples per
+    /// pixel. See `set_sample_count`.
+    pub async fn create(window: &Window, sample_count: u32) -> Result<RenderContext, RenderError> {
+        let gpu_context = crate::gpu::GpuContext::create(window).await?;
+        Self::with_gpu_context(gpu_context, sample_count)
+    }
+
+    /// Create a `RenderContext` which renders into an offscreen texture of the given size instead of
+    /// a window. Rendered frames can be read back with `read_pixels`.
+    pub async fn create_headless(width: u32, height: u32, sample_count: u32) -> Result<RenderContext, RenderError> {
+        let gpu_context = crate::gpu::GpuContext::create_headless(width, height).await?;
+        Self::with_gpu_context(gpu_context, sample_count)
+    }
+
+    fn with_gpu_context(gpu_context: crate::gpu::GpuContext, sample_count: u32) -> Result<RenderContext, RenderError> {
+        let sample_count = supported_sample_count(sample_count);
 
         // Create the command encoder used during initialization.
         let init_encoder = gpu_context.create_command_encoder();
 
-        let world_geometry_manager = crate::world_geometry::WorldGeometryManager::new(&gpu_context)?;
-
         // Load the vertex and fragment shaders.
-        let vs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/shader.vert.spv"));
+        let vs_module = gpu_context.create_shader_module_from_bytes(
+            <crate::world_geometry::Vertex as crate::mesh::MeshVertex>::VERTEX_SHADER,
+        );
         let fs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/shader.frag.spv"));
 
-        // Create our texture and write it into a GPU buffer. Right now the texture is just a white image, but the
-        // infrastructure is already in place to make better use of this data.
-        let size = 64u32;
-        let texels = utils::load_image_bytes("texture.png");
-        let texture_extent = wgpu::Extent3d {
-            width: size,
-            height: size,
-            depth: 1,
-        };
+        // Load the block textures, and write them into the layers of a GPU texture array, one mip
+        // level at a time. The block registry looks up the layer of each of its blocks' textures.
+        let textures = crate::texture_array::TextureArray::load(TEXTURE_DIR)?;
+        let block_registry = crate::block::BlockRegistry::new(&textures)?;
         let texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
-            size: texture_extent,
-            mip_level_count: 1,
+            size: wgpu::Extent3d {
+                width: textures.size(),
+                height: textures.size(),
+                depth: textures.len() as u32,
+            },
+            mip_level_count: textures.mip_level_count(),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
             label: None,
         });
-        let texture_view = texture.create_default_view();
-        // Place the texture data into a temporary copy buffer, and then immediately request a copy of it into a texture
-        // buffer on the GPU. We wrap this in a lexical scope to avoid reusing `temp_buf`.
-        gpu_context.queue().write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &texels,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * size,
-                rows_per_image: 0,
-            },
-            texture_extent,
-        );
-
-        let (sc_width, sc_height) = gpu_context.size();
-        // Create our depth buffer.
-        let depth_buffer_size = wgpu::Extent3d {
-            width: sc_width,
-            height: sc_height,
-            depth: 1,
-        };
-        let depth_buffer = gpu_context.create_texture(&wgpu::TextureDescriptor {
-            size: depth_buffer_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_SRC,
+        // A texture with a single layer would get a plain 2D view by default, so ask for an array.
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
             label: None,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            dimension: wgpu::TextureViewDimension::D2Array,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            level_count: textures.mip_level_count(),
+            base_array_layer: 0,
+            array_layer_count: textures.len() as u32,
         });
-        let depth_buffer_view = depth_buffer.create_default_view();
+        for layer in 0..textures.len() as u32 {
+            for (mip_level, mip) in textures.mips(layer).iter().enumerate() {
+                gpu_context.queue().write_texture(
+                    wgpu::TextureCopyView {
+                        texture: &texture,
+                        mip_level: mip_level as u32,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                    },
+                    mip.as_raw(),
+                    wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: 4 * mip.width(),
+                        rows_per_image: 0,
+                    },
+                    wgpu::Extent3d {
+                        width: mip.width(),
+                        height: mip.height(),
+                        depth: 1,
+                    },
+                );
+            }
+        }
 
-        // Create the samplers.
-        let depth_buffer_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            ..Default::default()
-        });
+        // Create our depth buffer.
+        let (depth_buffer, depth_buffer_view) = create_depth_buffer(&gpu_context, sample_count);
+        let multisampled_framebuffer = create_multisampled_framebuffer(&gpu_context, sample_count);
 
+        // Create the samplers.
+        // Block textures repeat across faces which cover several blocks. Up close they keep their
+        // crisp pixels, and in the distance they are filtered trilinearly between mip levels.
         let texture_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
             label: None,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
@@ -159,11 +236,37 @@ impl RenderContext {
         );
         let camera_matrix: crate::utils::Matrix4 = camera.matrix().into();
 
+        // Load the world geometry surrounding the camera.
+        let world_geometry_manager = crate::world_geometry::WorldGeometryManager::new(
+            &gpu_context,
+            block_registry,
+            camera.position(),
+            DEFAULT_VIEW_RADIUS,
+            DEFAULT_VIEW_RADIUS_Z,
+        )?;
+
         // Create the GPU buffer where we will store our shader uniforms.
         let uniform_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
             &gpu_context,
             camera_matrix,
-        ).ok()?;
+        )?;
+        let view_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
+            &gpu_context,
+            utils::ViewUniforms::new(camera.matrix(), camera.position()),
+        )?;
+        let sun = lighting::Sun::default();
+        let atmosphere = lighting::Atmosphere::default();
+        let lighting_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
+            &gpu_context,
+            lighting::LightingUniforms::new(&sun, &atmosphere),
+        )?;
+        let shadow_map = shadow::ShadowMap::new(
+            &gpu_context,
+            &vs_module,
+            &world_geometry_manager.transforms_buf,
+            &mut camera,
+            &sun,
+        )?;
 
         // Set up our bind groups; this binds our data to named locations which are referenced in the shaders.
         let bind_group_layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -179,16 +282,15 @@ impl RenderContext {
                         min_binding_size: wgpu::BufferSize::new(64),
                     },
                 ),
-                // Our 1st bind group is for texture data, which will be passed as an atlas. This may change a few times
-                // per frame if we need to render from multiple atlases. TODO: are texture atlases the right way to do
-                // this?
+                // Our 1st bind group is for texture data, which is passed as a texture array with a layer for
+                // each block texture.
                 wgpu::BindGroupLayoutEntry::new(
                     1,
                     wgpu::ShaderStage::FRAGMENT,
                     wgpu::BindingType::SampledTexture {
                         multisampled: false,
                         component_type: wgpu::TextureComponentType::Float,
-                        dimension: wgpu::TextureViewDimension::D2,
+                        dimension: wgpu::TextureViewDimension::D2Array,
                     },
                 ),
                 // Our 2nd bind group is the sampler for the above texture. This is likely to change only when the
@@ -208,112 +310,72 @@ impl RenderContext {
                         min_binding_size: wgpu::BufferSize::new(256),
                     },
                 ),
+                // The lighting, which is shared by the whole frame like the camera, but only read while
+                // shading fragments.
+                wgpu::BindGroupLayoutEntry::new(
+                    4,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<lighting::LightingUniforms>() as u64),
+                    },
+                ),
+                // The shadow map, the comparison sampler which reads it, and the sun's matrix which
+                // finds each fragment in it.
+                wgpu::BindGroupLayoutEntry::new(
+                    5,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        component_type: wgpu::TextureComponentType::DepthComparison,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    6,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::Sampler { comparison: true },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    7,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: wgpu::BufferSize::new(64),
+                    },
+                ),
+                // Where the camera is, for working out how far away fragments are, and which way the
+                // sky is being looked at.
+                wgpu::BindGroupLayoutEntry::new(
+                    8,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<utils::ViewUniforms>() as u64),
+                    },
+                ),
             ],
         });
 
-        let bind_group = gpu_context.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(uniform_buf.slice(..)),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::Binding {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&texture_sampler),
-                },
-                wgpu::Binding {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Buffer(world_geometry_manager.transforms_buf.slice(..)),
-                },
-            ],
-            label: None,
-        });
+        let bind_group = create_bind_group(
+            &gpu_context,
+            &bind_group_layout,
+            &uniform_buf,
+            &texture_view,
+            &texture_sampler,
+            &world_geometry_manager.transforms_buf,
+            &lighting_buf,
+            &shadow_map,
+            &view_buf,
+        );
 
         // Set up our central render pipeline.
         let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&bind_group_layout],
         });
 
-        let render_pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                color_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::SrcAlpha,
-                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                alpha_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::One,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[wgpu::VertexBufferDescriptor {
-                    stride: utils::IVERTEX_SIZE as wgpu::BufferAddress,
-                    step_mode: wgpu::InputStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Int3,
-                            offset: 0,
-                            shader_location: 0,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Int3,
-                            offset: 4*3,
-                            shader_location: 1,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float2,
-                            offset: 4*3 + 4*3,
-                            shader_location: 2,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Uint,
-                            offset: 4*3 + 4*3 + 4*2,
-                            shader_location: 3,
-                        },
-                    ],
-                }],
-            },
-
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let render_pipeline = create_render_pipeline(&gpu_context, &pipeline_layout, &vs_module, &fs_module, sample_count);
+        let sky = sky::Sky::new(&gpu_context, &pipeline_layout, sample_count);
 
         // Flush the initialization commands on the command queue.
         gpu_context.queue().submit(Some(init_encoder.finish()));
@@ -321,7 +383,9 @@ impl RenderContext {
         let next_frame_encoder =
             gpu_context.create_command_encoder();
 
-        Some(Self {
+        let bind_group_transforms_generation = world_geometry_manager.transforms_buf.generation();
+
+        Ok(Self {
             gpu_context,
             next_frame_encoder,
             world_geometry_manager,
@@ -332,59 +396,207 @@ impl RenderContext {
             texture_sampler,
             depth_buffer,
             depth_buffer_view,
-            depth_buffer_sampler,
+            sample_count,
+            multisampled_framebuffer,
             camera,
             uniform_buf,
+            view_buf,
+            sun,
+            atmosphere,
+            lighting_buf,
+            shadow_map,
+            sky,
             bind_group_layout,
             bind_group,
+            bind_group_transforms_generation,
             pipeline_layout,
             render_pipeline,
             camera_dirty: false,
+            lighting_dirty: false,
+            minimized: false,
         })
     }
 
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        // A minimized window is resized to nothing. A swap chain can't be that small, so keep the
+        // old render targets around and stop rendering until the window comes back.
+        self.minimized = size.width == 0 || size.height == 0;
+        if self.minimized {
+            return;
+        }
+
         // Update our GPU context with the new width and height.
         self.gpu_context.resize(size);
 
+        // Everything we render into alongside the swap chain has to match its size.
+        self.create_render_targets();
+
         // Our aspect ratio might have changed, so we update our camera.
         self.camera.set_aspect_ratio(self.gpu_context.aspect_ratio());
+        self.set_camera_dirty();
     }
 
-    pub fn render(&mut self) {
-        let frame = self.gpu_context.get_next_frame().unwrap();
+    /// Returns the number of samples rendered per pixel.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
 
+    /// Sets the number of samples rendered per pixel, rebuilding the render targets and the
+    /// pipeline to match. One sample turns multisampling off. A count which isn't one of
+    /// `SUPPORTED_SAMPLE_COUNTS` falls back to the next lowest one which is.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        let sample_count = supported_sample_count(sample_count);
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.use_sample_count(sample_count);
+        log::info!("Rendering with {}x multisampling.", sample_count);
+    }
+
+    /// Rebuild the render targets and the pipelines for `sample_count` samples per pixel, which must
+    /// be one of `SUPPORTED_SAMPLE_COUNTS`.
+    fn use_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+
+        self.create_render_targets();
+        self.render_pipeline = create_render_pipeline(
+            &self.gpu_context,
+            &self.pipeline_layout,
+            &self.vs_module,
+            &self.fs_module,
+            sample_count,
+        );
+        self.sky.set_sample_count(&self.gpu_context, &self.pipeline_layout, sample_count);
+    }
+
+    /// Rebuild every render target which has to match the size and sample count of the frame.
+    fn create_render_targets(&mut self) {
+        let (depth_buffer, depth_buffer_view) = create_depth_buffer(&self.gpu_context, self.sample_count);
+        self.depth_buffer = depth_buffer;
+        self.depth_buffer_view = depth_buffer_view;
+        self.multisampled_framebuffer = create_multisampled_framebuffer(&self.gpu_context, self.sample_count);
+    }
+
+    /// Render a frame and present it. A frame which can't be rendered right now, because the window
+    /// is minimized, or the swap chain timed out or had to be recreated, is skipped without an
+    /// error.
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        if self.minimized {
+            return Ok(());
+        }
+
+        let frame = match self.gpu_context.get_next_frame() {
+            Ok(frame) => frame,
+            Err(GpuContextError::SwapChainError(wgpu::SwapChainError::Timeout)) => return Ok(()),
+            Err(GpuContextError::SwapChainError(wgpu::SwapChainError::Outdated))
+            | Err(GpuContextError::SwapChainError(wgpu::SwapChainError::Lost)) => {
+                log::warn!("Swap chain is out of date; recreating it.");
+                self.gpu_context.recreate_swap_chain();
+                return Ok(());
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        self.prepare_frame();
+        self.encode_shadow_pass();
+        self.encode_world_pass(frame.view());
+        self.submit_frame();
+
+        Ok(())
+    }
+
+    /// Stream world geometry in around the camera, and bring everything the world pass reads from up
+    /// to date on the GPU.
+    fn prepare_frame(&mut self) {
+        self.stream_world_geometry();
+        self.flush_frame_data();
+    }
+
+    /// Load and unload world geometry around the camera.
+    fn stream_world_geometry(&mut self) {
+        // If the transforms buffer had to grow, the bind group is still pointing at the old one and
+        // must be rebuilt.
+        self.world_geometry_manager.update(&self.gpu_context, self.camera.position());
+        let transforms_generation = self.world_geometry_manager.transforms_buf.generation();
+        if transforms_generation != self.bind_group_transforms_generation {
+            self.bind_group = create_bind_group(
+                &self.gpu_context,
+                &self.bind_group_layout,
+                &self.uniform_buf,
+                &self.texture_view,
+                &self.texture_sampler,
+                &self.world_geometry_manager.transforms_buf,
+                &self.lighting_buf,
+                &self.shadow_map,
+                &self.view_buf,
+            );
+            self.shadow_map.rebind(&self.gpu_context, &self.world_geometry_manager.transforms_buf);
+            self.bind_group_transforms_generation = transforms_generation;
+        }
+    }
+
+    /// Write the camera, the lighting, and any changed world geometry to the GPU, without loading or
+    /// unloading any of it.
+    fn flush_frame_data(&mut self) {
         // If the camera moved, we have to write the camera's data into the uniform buffer. We write
         // the data into the CPU side of our managed uniform buffer here.
         if self.camera_dirty {
             self.uniform_buf.replace_data(self.camera.matrix().into());
+            self.view_buf.replace_data(utils::ViewUniforms::new(self.camera.matrix(), self.camera.position()));
+        }
+        // The shadow map follows the camera, and is drawn from the sun.
+        if self.camera_dirty || self.lighting_dirty {
+            self.shadow_map.fit(&mut self.camera, &self.sun);
+        }
+        self.camera_dirty = false;
+        if self.lighting_dirty {
+            self.lighting_buf.replace_data(lighting::LightingUniforms::new(&self.sun, &self.atmosphere));
+            self.lighting_dirty = false;
         }
 
         // This looks weird, but picture the future: a loop over some collection of buffers,
         // potentially flushing each one.
         if self.world_geometry_manager.vertex_buf.dirty() {
-            self.world_geometry_manager.vertex_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
+            self.world_geometry_manager.vertex_buf.flush(&self.gpu_context);
         }
         if self.world_geometry_manager.index_buf.dirty() {
-            self.world_geometry_manager.index_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
+            self.world_geometry_manager.index_buf.flush(&self.gpu_context);
+        }
+        if self.world_geometry_manager.transforms_buf.dirty() {
+            self.world_geometry_manager.transforms_buf.flush(&self.gpu_context);
         }
         if self.uniform_buf.dirty() {
-            self.uniform_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
+            self.uniform_buf.flush(&self.gpu_context);
+        }
+        if self.view_buf.dirty() {
+            self.view_buf.flush(&self.gpu_context);
         }
+        if self.lighting_buf.dirty() {
+            self.lighting_buf.flush(&self.gpu_context);
+        }
+        self.shadow_map.flush(&self.gpu_context);
+    }
 
+    /// Record a pass drawing the world into `target` on the frame's command encoder.
+    fn encode_world_pass(&mut self, target: &wgpu::TextureView) {
+        // Chunks entirely outside of the camera's view are not drawn.
+        let frustum = self.camera.frustum();
+
+        // When multisampling, the samples are drawn into their own framebuffer, and then resolved
+        // into the target.
+        let (attachment, resolve_target) = match &self.multisampled_framebuffer {
+            Some((_, view)) => (view, Some(target)),
+            None => (target, None),
+        };
 
         {
             let mut render_pass = self.next_frame_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.output.view,
-                    resolve_target: None,
+                    attachment,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        // The sky is drawn over every pixel, so what this is cleared to never shows.
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: true,
                     }
                 }],
@@ -400,11 +612,16 @@ impl RenderContext {
                     }),
                 }),
             });
+            self.sky.draw(&mut render_pass, &self.bind_group);
+
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_index_buffer(self.world_geometry_manager.index_buf.slice(..));
             render_pass.set_vertex_buffer(0, self.world_geometry_manager.vertex_buf.slice(..));
-            for i in 0..self.world_geometry_manager.chunks.len() as u32 {
-                let chunk = &self.world_geometry_manager.chunks[i as usize];
+            for chunk in self.world_geometry_manager.chunks.values() {
+                let (min, max) = chunk.bounds();
+                if !frustum.intersects_aabb(min, max) {
+                    continue;
+                }
                 render_pass.set_bind_group(
                     0,
                     &self.bind_group,
@@ -413,7 +630,10 @@ impl RenderContext {
                 render_pass.draw_indexed(chunk.index_offset as u32..(chunk.index_offset + chunk.index_count) as u32, chunk.vertex_offset as i32, 0..1);
             }
         }
+    }
 
+    /// Submit everything recorded on the frame's command encoder.
+    fn submit_frame(&mut self) {
         // Pull out the command encoder we have been using to build up this frame. We set up the next frame's encoder
         // at the same time.
         let final_encoder = std::mem::replace(
@@ -424,6 +644,18 @@ impl RenderContext {
         self.gpu_context.submit_command_encoder(final_encoder);
     }
 
+    /// Copies the last rendered frame back from the GPU. This only works for a `RenderContext`
+    /// created with `create_headless`.
+    pub fn read_pixels(&self) -> Result<image::RgbaImage, GpuContextError> {
+        self.gpu_context.read_pixels()
+    }
+
+    /// Returns true while the world around the camera is still streaming in, and so is not fully
+    /// drawn yet.
+    pub fn is_world_loading(&self) -> bool {
+        self.world_geometry_manager.is_loading()
+    }
+
     // Expose raw mutation for some of the basic state variables.
     fn set_camera_dirty(&mut self) {
         self.camera_dirty = true;
@@ -440,4 +672,213 @@ impl RenderContext {
         self.set_camera_dirty();
         &mut self.camera
     }
+
+    #[allow(dead_code)]
+    pub fn sun(&self) -> &lighting::Sun {
+        &self.sun
+    }
+    /// Like `camera_mut`, this marks the lighting to be written to the GPU before the next frame.
+    pub fn sun_mut(&mut self) -> &mut lighting::Sun {
+        self.lighting_dirty = true;
+        &mut self.sun
+    }
+
+    #[allow(dead_code)]
+    pub fn atmosphere(&self) -> &lighting::Atmosphere {
+        &self.atmosphere
+    }
+    /// Like `sun_mut`, this marks the lighting to be written to the GPU before the next frame.
+    pub fn atmosphere_mut(&mut self) -> &mut lighting::Atmosphere {
+        self.lighting_dirty = true;
+        &mut self.atmosphere
+    }
+}
+
+/// Create the pipeline which draws the world, rendering `sample_count` samples per pixel. This needs
+/// to be called again whenever the sample count changes.
+fn create_render_pipeline(
+    gpu_context: &crate::gpu::GpuContext,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout: pipeline_layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format: crate::gpu::FRAME_FORMAT,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: <crate::world_geometry::Index as crate::mesh::MeshIndex>::FORMAT,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: std::mem::size_of::<crate::world_geometry::Vertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: <crate::world_geometry::Vertex as crate::mesh::MeshVertex>::ATTRIBUTES,
+            }],
+        },
+
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+/// Returns the highest of `SUPPORTED_SAMPLE_COUNTS` which is no more than `sample_count`, warning
+/// if that isn't `sample_count` itself.
+fn supported_sample_count(sample_count: u32) -> u32 {
+    let supported = SUPPORTED_SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .filter(|&count| count <= sample_count)
+        .max()
+        .unwrap_or(1);
+    if supported != sample_count {
+        log::warn!("{}x multisampling is not supported; falling back to {}x.", sample_count, supported);
+    }
+    supported
+}
+
+/// Create the framebuffer which the world is drawn into when multisampling, or `None` if
+/// `sample_count` is one.
+fn create_multisampled_framebuffer(
+    gpu_context: &crate::gpu::GpuContext,
+    sample_count: u32,
+) -> Option<(wgpu::Texture, wgpu::TextureView)> {
+    if sample_count == 1 {
+        return None;
+    }
+
+    let (width, height) = gpu_context.size();
+    let framebuffer = gpu_context.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: crate::gpu::FRAME_FORMAT,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        label: None,
+    });
+    let view = framebuffer.create_default_view();
+
+    Some((framebuffer, view))
+}
+
+/// Create a depth buffer the size of the render target, along with a view of it. The depth buffer
+/// must have as many samples as the color attachment it is used with.
+fn create_depth_buffer(gpu_context: &crate::gpu::GpuContext, sample_count: u32) -> (wgpu::Texture, wgpu::TextureView) {
+    let (width, height) = gpu_context.size();
+    let depth_buffer = gpu_context.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Depth32Float,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_SRC,
+        label: None,
+    });
+    let depth_buffer_view = depth_buffer.create_default_view();
+
+    (depth_buffer, depth_buffer_view)
+}
+
+/// Bind our data to the named locations described by `layout`. This needs to be called again
+/// whenever any of the bound buffers are replaced.
+#[allow(clippy::too_many_arguments)]
+fn create_bind_group(
+    gpu_context: &crate::gpu::GpuContext,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buf: &crate::managed_buffer::ManagedBuffer<f32, utils::Matrix4>,
+    texture_view: &wgpu::TextureView,
+    texture_sampler: &wgpu::Sampler,
+    transforms_buf: &crate::managed_buffer::ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+    lighting_buf: &crate::managed_buffer::ManagedBuffer<f32, lighting::LightingUniforms>,
+    shadow_map: &shadow::ShadowMap,
+    view_buf: &crate::managed_buffer::ManagedBuffer<f32, utils::ViewUniforms>,
+) -> wgpu::BindGroup {
+    gpu_context.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        bindings: &[
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(uniform_buf.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            wgpu::Binding {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(texture_sampler),
+            },
+            wgpu::Binding {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(transforms_buf.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(lighting_buf.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(shadow_map.view()),
+            },
+            wgpu::Binding {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(shadow_map.sampler()),
+            },
+            wgpu::Binding {
+                binding: 7,
+                resource: wgpu::BindingResource::Buffer(shadow_map.matrix_buf().slice(..)),
+            },
+            wgpu::Binding {
+                binding: 8,
+                resource: wgpu::BindingResource::Buffer(view_buf.slice(..)),
+            },
+        ],
+        label: None,
+    })
 }
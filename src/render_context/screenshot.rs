@@ -0,0 +1,146 @@
+/// Screenshots of the live renderer. The swap chain's images can't be copied out of, so a
+/// screenshot draws the world once more into a texture which can, and reads that back along with
+/// the depth buffer.
+
+use std::path::PathBuf;
+
+use crate::gpu::{self, GpuContextError};
+
+use super::RenderContext;
+
+/// Screenshots are saved into this directory, relative to the working directory.
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// A single frame copied back from the GPU.
+pub struct Screenshot {
+    pub color: image::RgbaImage,
+    /// The depth of each pixel, scaled linearly between the camera's near and far planes, so that
+    /// the sky is white.
+    pub depth: Option<image::ImageBuffer<image::Luma<u16>, Vec<u16>>>,
+}
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    GpuError(GpuContextError),
+    IoError(std::io::Error),
+    ImageError(image::ImageError),
+}
+
+impl std::fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenshotError::GpuError(err) => write!(f, "Reading the frame back failed: {}", err),
+            ScreenshotError::IoError(err) => write!(f, "Writing the screenshot failed: {}", err),
+            ScreenshotError::ImageError(err) => write!(f, "Encoding the screenshot failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+impl From<GpuContextError> for ScreenshotError {
+    fn from(err: GpuContextError) -> Self {
+        ScreenshotError::GpuError(err)
+    }
+}
+
+impl From<std::io::Error> for ScreenshotError {
+    fn from(err: std::io::Error) -> Self {
+        ScreenshotError::IoError(err)
+    }
+}
+
+impl From<image::ImageError> for ScreenshotError {
+    fn from(err: image::ImageError) -> Self {
+        ScreenshotError::ImageError(err)
+    }
+}
+
+impl RenderContext {
+    /// Render the current frame again and copy it back from the GPU, along with the depth buffer if
+    /// `include_depth` is set. Nothing is presented to the window. This blocks until the GPU has
+    /// caught up.
+    ///
+    /// A multisampled depth buffer can't be copied, so a screenshot which includes the depth buffer
+    /// is drawn with a single sample per pixel, whatever the multisampling level.
+    pub fn take_screenshot(&mut self, include_depth: bool) -> Result<Screenshot, ScreenshotError> {
+        let sample_count = self.sample_count;
+        let single_sample = include_depth && sample_count > 1;
+        if single_sample {
+            self.use_sample_count(1);
+        }
+        let screenshot = self.capture(include_depth);
+        if single_sample {
+            self.use_sample_count(sample_count);
+        }
+        screenshot
+    }
+
+    /// Render the current frame into a texture, and read it back. See `take_screenshot`.
+    fn capture(&mut self, include_depth: bool) -> Result<Screenshot, ScreenshotError> {
+        let (width, height) = self.gpu_context.size();
+        let capture = self.gpu_context.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: gpu::FRAME_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            label: None,
+        });
+
+        // Taking a screenshot shouldn't load or unload any of the world, so only the frame's data is
+        // brought up to date.
+        self.flush_frame_data();
+        self.encode_shadow_pass();
+        self.encode_world_pass(&capture.create_default_view());
+        self.submit_frame();
+
+        let texels = self.gpu_context.read_texture(&capture, width, height, 4)?;
+        let color = gpu::bgra_to_rgba_image(width, height, texels);
+
+        let depth = if include_depth {
+            let texels = self.gpu_context.read_texture(&self.depth_buffer, width, height, 4)?;
+            let (z_near, z_far) = self.camera.depth_range();
+            let depths = texels
+                .chunks(4)
+                .map(|bytes| {
+                    let d = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    // Undo the perspective divide to recover the distance from the camera.
+                    let z = z_near * z_far / (z_far - d * (z_far - z_near));
+                    ((z - z_near) / (z_far - z_near) * u16::MAX as f32) as u16
+                })
+                .collect();
+            Some(image::ImageBuffer::from_raw(width, height, depths).expect("depth buffer has the wrong size"))
+        } else {
+            None
+        };
+
+        Ok(Screenshot { color, depth })
+    }
+
+    /// Take a screenshot and save it into the screenshots directory as a PNG named after the
+    /// current time. The depth image, if there is one, is saved next to it with a `-depth` suffix.
+    /// Returns the path of the color image.
+    pub fn save_screenshot(&mut self, include_depth: bool) -> Result<PathBuf, ScreenshotError> {
+        let screenshot = self.take_screenshot(include_depth)?;
+
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |t| t.as_millis());
+        let dir = PathBuf::from(SCREENSHOT_DIR);
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir.join(format!("screenshot-{}.png", millis));
+        screenshot.color.save(&path)?;
+        if let Some(depth) = screenshot.depth {
+            depth.save(dir.join(format!("screenshot-{}-depth.png", millis)))?;
+        }
+
+        Ok(path)
+    }
+}
@@ -0,0 +1,259 @@
+/// Shadows cast by the sun. Before the world is drawn, its depth is drawn from the sun's point of
+/// view into a shadow map, which the world pass then compares each fragment against to find out
+/// whether the sun can see it.
+
+use crate::managed_buffer::{ManagedBuffer, ManagedBufferError};
+use crate::utils;
+
+use super::RenderContext;
+
+/// The width and height of the shadow map, in texels.
+const SHADOW_MAP_SIZE: u32 = 2048;
+/// Shadows are only drawn for the part of the camera's view within this distance of the camera.
+/// Spreading the shadow map over the whole view would leave too few texels for nearby shadows.
+const SHADOW_DISTANCE: f32 = 160.0;
+/// How far beyond the shadowed region, toward the sun, terrain is still drawn into the shadow map,
+/// so that hills outside of the camera's view can cast shadows into it.
+const CASTER_MARGIN: f32 = 128.0;
+
+pub struct ShadowMap {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// Compares against the shadow map rather than reading it. Linear filtering blends the results
+    /// of the four nearest comparisons.
+    sampler: wgpu::Sampler,
+
+    /// The sun's view projection matrix. This is the camera of the shadow pass, and tells the world
+    /// pass where each fragment falls in the shadow map.
+    matrix: cgmath::Matrix4<f32>,
+    matrix_buf: ManagedBuffer<f32, utils::Matrix4>,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    /// Create the shadow map, fitted to the camera's view, along with the pipeline which draws
+    /// into it. The pipeline draws world geometry with `vs_module`, the world pass's own vertex
+    /// shader, bound to the sun's matrix in place of the camera's.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        vs_module: &wgpu::ShaderModule,
+        transforms_buf: &ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+        camera: &mut crate::camera::Camera,
+        sun: &crate::lighting::Sun,
+    ) -> Result<Self, ManagedBufferError> {
+        let texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            label: None,
+        });
+        let view = texture.create_default_view();
+
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let matrix = fitted_matrix(camera, sun);
+        let matrix_buf = ManagedBuffer::new_uniform_buf_with_data(gpu_context, matrix.into())?;
+
+        // The world's vertex shader only reads the camera and the transforms, so those are the only
+        // bindings the shadow pass needs.
+        let bind_group_layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::VERTEX,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: wgpu::BufferSize::new(64),
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    3,
+                    wgpu::ShaderStage::VERTEX,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: true,
+                        min_binding_size: wgpu::BufferSize::new(256),
+                    },
+                ),
+            ],
+        });
+        let bind_group = create_bind_group(gpu_context, &bind_group_layout, &matrix_buf, transforms_buf);
+
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: vs_module,
+                entry_point: "main",
+            },
+            // Only depth is drawn, so there is nothing for a fragment shader to do.
+            fragment_stage: None,
+            // Depth is biased away from the sun, more so on surfaces which are steep to it, to keep
+            // surfaces from shadowing themselves.
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                depth_bias: 2,
+                depth_bias_slope_scale: 2.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: <crate::world_geometry::Index as crate::mesh::MeshIndex>::FORMAT,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<crate::world_geometry::Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: <crate::world_geometry::Vertex as crate::mesh::MeshVertex>::ATTRIBUTES,
+                }],
+            },
+
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            matrix,
+            matrix_buf,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    pub fn matrix_buf(&self) -> &ManagedBuffer<f32, utils::Matrix4> {
+        &self.matrix_buf
+    }
+
+    /// Fit the shadow map to the part of the camera's view within `SHADOW_DISTANCE`, as seen from
+    /// the sun. This has to be called whenever the camera or the sun moves.
+    pub fn fit(&mut self, camera: &mut crate::camera::Camera, sun: &crate::lighting::Sun) {
+        self.matrix = fitted_matrix(camera, sun);
+        self.matrix_buf.replace_data(self.matrix.into());
+    }
+
+    /// Rebind the transforms buffer. This needs to be called whenever it is replaced.
+    pub fn rebind(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        transforms_buf: &ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+    ) {
+        self.bind_group = create_bind_group(gpu_context, &self.bind_group_layout, &self.matrix_buf, transforms_buf);
+    }
+
+    /// Write the sun's matrix to the GPU, if it has changed.
+    pub fn flush(&mut self, gpu_context: &crate::gpu::GpuContext) {
+        if self.matrix_buf.dirty() {
+            self.matrix_buf.flush(gpu_context);
+        }
+    }
+}
+
+impl RenderContext {
+    /// Record a pass drawing the depth of the world, as seen from the sun, into the shadow map on
+    /// the frame's command encoder. This has to come before the world pass which reads it.
+    pub(super) fn encode_shadow_pass(&mut self) {
+        // Chunks which can't cast a shadow into the shadow map are not drawn.
+        let frustum = crate::camera::Frustum::from_matrix(self.shadow_map.matrix);
+
+        let mut render_pass = self.next_frame_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.shadow_map.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.shadow_map.pipeline);
+        render_pass.set_index_buffer(self.world_geometry_manager.index_buf.slice(..));
+        render_pass.set_vertex_buffer(0, self.world_geometry_manager.vertex_buf.slice(..));
+        for chunk in self.world_geometry_manager.chunks.values() {
+            let (min, max) = chunk.bounds();
+            if !frustum.intersects_aabb(min, max) {
+                continue;
+            }
+            render_pass.set_bind_group(
+                0,
+                &self.shadow_map.bind_group,
+                &[(chunk.transform_index * self.world_geometry_manager.transforms_buf.t_size()) as u32],
+            );
+            render_pass.draw_indexed(chunk.index_offset as u32..(chunk.index_offset + chunk.index_count) as u32, chunk.vertex_offset as i32, 0..1);
+        }
+    }
+}
+
+fn fitted_matrix(camera: &mut crate::camera::Camera, sun: &crate::lighting::Sun) -> cgmath::Matrix4<f32> {
+    let corners = camera.frustum_corners(SHADOW_DISTANCE);
+    sun.shadow_matrix(&corners, CASTER_MARGIN, SHADOW_MAP_SIZE)
+}
+
+fn create_bind_group(
+    gpu_context: &crate::gpu::GpuContext,
+    layout: &wgpu::BindGroupLayout,
+    matrix_buf: &ManagedBuffer<f32, utils::Matrix4>,
+    transforms_buf: &ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+) -> wgpu::BindGroup {
+    gpu_context.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        bindings: &[
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(matrix_buf.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(transforms_buf.slice(..)),
+            },
+        ],
+        label: None,
+    })
+}
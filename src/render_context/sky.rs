@@ -0,0 +1,102 @@
+/// The sky, drawn behind the world at the start of the world pass. A single triangle covers the
+/// whole frame, and the fragment shader works out which way each pixel looks to color it with a
+/// gradient from the horizon to the zenith, and the sun.
+
+pub struct Sky {
+    vs_module: wgpu::ShaderModule,
+    fs_module: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Sky {
+    /// Create the sky's pipeline. The sky reads the lighting and the camera from the world pass's
+    /// bind group, so it shares the world's pipeline layout, and it has to be drawn with
+    /// `sample_count` samples per pixel to fit in the same pass.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> Self {
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/sky.vert.spv"));
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/sky.frag.spv"));
+        let pipeline = create_pipeline(gpu_context, pipeline_layout, &vs_module, &fs_module, sample_count);
+
+        Self {
+            vs_module,
+            fs_module,
+            pipeline,
+        }
+    }
+
+    /// Rebuild the pipeline for a new sample count. See `RenderContext::set_sample_count`.
+    pub fn set_sample_count(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) {
+        self.pipeline = create_pipeline(gpu_context, pipeline_layout, &self.vs_module, &self.fs_module, sample_count);
+    }
+
+    /// Draw the sky. This must come before anything else in the pass, since the sky ignores the
+    /// depth buffer and would cover it. `bind_group` is the world pass's bind group.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        // The sky doesn't read the per-chunk transforms, but their dynamic offset is still needed.
+        render_pass.set_bind_group(0, bind_group, &[0]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_pipeline(
+    gpu_context: &crate::gpu::GpuContext,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout: pipeline_layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::None,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format: crate::gpu::FRAME_FORMAT,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        // The sky is infinitely far away, so it neither tests against nor writes any depth.
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        // The triangle's corners are made up by the vertex shader, so there are no vertex buffers.
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[],
+        },
+
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
@@ -0,0 +1,158 @@
+/// Terrain generation. The world is described by a function from world positions to blocks: a
+/// simplex noise heightmap forms the ground, 3D noise carves caves out of it, and more 3D noise
+/// forms islands floating in a band of sky above it.
+
+use cgmath::Point3;
+
+use crate::block::{self, AIR, BlockId};
+use crate::chunk::{CHUNK_DIM, Chunk};
+use crate::simplex;
+
+/// Sky islands are only generated between these world heights.
+const ISLAND_MIN_Z: i32 = 48;
+const ISLAND_MAX_Z: i32 = 80;
+
+/// Samples the terrain within a single chunk. Column heights are cached for the chunk, so that the
+/// heightmap noise is only sampled once per column.
+pub struct TerrainSampler<'a> {
+    noise: &'a simplex::Simplex,
+    /// The world position of the chunk's voxel at the chunk-local origin.
+    origin: Point3<i32>,
+    heights: Vec<i32>,
+}
+
+impl<'a> TerrainSampler<'a> {
+    /// Create a new `TerrainSampler` for the chunk at the given chunk coordinates.
+    pub fn new(chunk_pos: Point3<i32>, noise: &'a simplex::Simplex) -> Self {
+        let dim = CHUNK_DIM as i32;
+        let origin = Point3::new(chunk_pos.x * dim, chunk_pos.y * dim, chunk_pos.z * dim);
+
+        let mut heights = Vec::with_capacity(CHUNK_DIM * CHUNK_DIM);
+        for x_i in 0..dim {
+            for y_i in 0..dim {
+                heights.push(terrain_height(origin.x + x_i, origin.y + y_i, noise));
+            }
+        }
+
+        Self {
+            noise,
+            origin,
+            heights,
+        }
+    }
+
+    /// Returns the terrain height of the column at the chunk-local coordinates.
+    fn height(&self, x: i32, y: i32) -> i32 {
+        self.heights[x as usize * CHUNK_DIM + y as usize]
+    }
+
+    /// Returns true if nothing in the chunk can be solid. This lets us skip sampling 3D noise for
+    /// the large volume of open sky.
+    pub fn is_sky(&self) -> bool {
+        let min_z = self.origin.z;
+        let max_z = self.origin.z + CHUNK_DIM as i32 - 1;
+        let max_height = self.heights.iter().copied().max().unwrap();
+
+        min_z > max_height && (min_z > ISLAND_MAX_Z || max_z < ISLAND_MIN_Z)
+    }
+
+    /// Returns the block at the chunk-local position.
+    pub fn block_at(&self, pos: Point3<i32>) -> BlockId {
+        let surface = self.height(pos.x, pos.y);
+        let (x, y, z) = (self.origin.x + pos.x, self.origin.y + pos.y, self.origin.z + pos.z);
+
+        if z <= surface {
+            if is_cave(x, y, z, self.noise) {
+                AIR
+            } else {
+                ground_block(z, surface)
+            }
+        } else if is_island(x, y, z, self.noise) {
+            // Islands are capped with grass wherever they are open to the sky.
+            if is_island(x, y, z + 1, self.noise) { block::DIRT } else { block::GRASS }
+        } else {
+            AIR
+        }
+    }
+}
+
+/// Sample the terrain height at the given world column.
+fn terrain_height(x: i32, y: i32, noise: &simplex::Simplex) -> i32 {
+    // The noise functions are sampled in units of chunks.
+    let x = x as f64 / CHUNK_DIM as f64;
+    let y = y as f64 / CHUNK_DIM as f64;
+
+    let z1 = (noise.get2d(x / 2.0, y / 2.0) * 20.0) as f32;
+    let mult = (noise.get2d(x, y) * 2.0) as f32;
+    let extremes = (noise.get2d(x / 10.0, y / 10.0) * 10.0) as f32;
+
+    (z1 * mult * extremes).max(-1.0) as i32
+}
+
+/// Returns true if the world position, which lies beneath the terrain surface, has been carved out
+/// by a cave. Caves are the tunnels where two independent noise fields are both close to zero.
+fn is_cave(x: i32, y: i32, z: i32, noise: &simplex::Simplex) -> bool {
+    // Keep a floor of solid ground under the lowest terrain so that caves don't punch holes straight
+    // through the lakes.
+    if z >= -2 {
+        return false;
+    }
+
+    let (x, y, z) = (x as f64 / 32.0, y as f64 / 32.0, z as f64 / 24.0);
+    let a = noise.get3d(x, y, z);
+    if a.abs() > 0.08 {
+        return false;
+    }
+    // Offset the second sample far away so that the two fields are unrelated.
+    let b = noise.get3d(x + 1000.0, y + 1000.0, z + 1000.0);
+    b.abs() < 0.08
+}
+
+/// Returns true if the world position lies inside of a sky island.
+fn is_island(x: i32, y: i32, z: i32, noise: &simplex::Simplex) -> bool {
+    if z < ISLAND_MIN_Z || z > ISLAND_MAX_Z {
+        return false;
+    }
+
+    // Islands thin out toward the top and bottom of their band.
+    let band_center = (ISLAND_MIN_Z + ISLAND_MAX_Z) as f64 / 2.0;
+    let band_half = (ISLAND_MAX_Z - ISLAND_MIN_Z) as f64 / 2.0;
+    let falloff = ((z as f64 - band_center) / band_half).powi(2);
+
+    let density = noise.get3d(x as f64 / 40.0, y as f64 / 40.0, z as f64 / 20.0 - 500.0);
+    density - 0.4 * falloff > 0.35
+}
+
+/// Choose the block for a solid voxel of the ground given: its world height and the height of the
+/// terrain surface in its column.
+fn ground_block(z: i32, surface: i32) -> BlockId {
+    if z >= 15 {
+        block::SNOW
+    } else if z >= 0 {
+        if z == surface { block::GRASS } else { block::DIRT }
+    } else {
+        block::WATER
+    }
+}
+
+/// Fill a chunk with voxels from the terrain sampled by `sampler`.
+pub fn generate_chunk(sampler: &TerrainSampler) -> Chunk {
+    let mut chunk = Chunk::new();
+    if sampler.is_sky() {
+        return chunk;
+    }
+
+    for x_i in 0..CHUNK_DIM as i32 {
+        for y_i in 0..CHUNK_DIM as i32 {
+            for z_i in 0..CHUNK_DIM as i32 {
+                let pos = Point3::new(x_i, y_i, z_i);
+                let block = sampler.block_at(pos);
+                if block != AIR {
+                    chunk.set(pos, block);
+                }
+            }
+        }
+    }
+
+    chunk
+}
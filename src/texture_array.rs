@@ -0,0 +1,191 @@
+/// Loads a directory of block textures to be uploaded as the layers of a texture array. Each texture
+/// is looked up by its file name without the extension (so `grass.png` is the layer `grass`), and
+/// comes with a full chain of mip levels, generated when it is loaded.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Layer indices are stored in a byte of each vertex, which limits how many layers there may be.
+pub const MAX_LAYERS: usize = 256;
+
+#[derive(Debug)]
+pub enum TextureError {
+    IoError(PathBuf, std::io::Error),
+    ImageError(PathBuf, image::ImageError),
+    /// Every texture must be a square, and the same size as every other texture.
+    WrongSize {
+        path: PathBuf,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    NoTextures(PathBuf),
+    TooManyTextures(usize),
+    /// A block refers to a texture which wasn't loaded.
+    MissingTexture(String),
+}
+
+impl std::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureError::IoError(path, err) =>
+                write!(f, "Reading `{}` failed: {}", path.display(), err),
+            TextureError::ImageError(path, err) =>
+                write!(f, "Loading the texture `{}` failed: {}", path.display(), err),
+            TextureError::WrongSize { path, expected, found } =>
+                write!(
+                    f,
+                    "The texture `{}` is {}x{}, but every texture must be {}x{}.",
+                    path.display(), found.0, found.1, expected.0, expected.1,
+                ),
+            TextureError::NoTextures(path) =>
+                write!(f, "There are no PNG textures in `{}`.", path.display()),
+            TextureError::TooManyTextures(count) =>
+                write!(f, "There are {} textures, but a texture array holds at most {}.", count, MAX_LAYERS),
+            TextureError::MissingTexture(name) =>
+                write!(f, "There is no texture named `{}`.", name),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+pub struct TextureArray {
+    /// The mip chain of each layer, starting from the full size texture and halving down to 1x1.
+    layers: Vec<Vec<image::RgbaImage>>,
+    /// The width and height of every layer, in pixels, at the first mip level.
+    size: u32,
+    /// Maps each texture's name to its layer index.
+    layer_indices: HashMap<String, u32>,
+}
+
+#[allow(dead_code)]
+impl TextureArray {
+    /// Load every PNG in `dir` as a layer, and generate its mip levels. Textures are placed in
+    /// order of their names, so the same directory always produces the same layers.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, TextureError> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir).map_err(|e| TextureError::IoError(dir.to_owned(), e))?;
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| TextureError::IoError(dir.to_owned(), e))?.path();
+            if path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("png")) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        if paths.is_empty() {
+            return Err(TextureError::NoTextures(dir.to_owned()));
+        }
+        if paths.len() > MAX_LAYERS {
+            return Err(TextureError::TooManyTextures(paths.len()));
+        }
+
+        let mut layers = Vec::with_capacity(paths.len());
+        let mut layer_indices = HashMap::with_capacity(paths.len());
+        let mut size = None;
+        for path in paths {
+            let image = image::open(&path).map_err(|e| TextureError::ImageError(path.clone(), e))?.to_rgba();
+
+            // Every layer takes on the size of the first one; the rest have to match it.
+            let size = *size.get_or_insert(image.width());
+            if image.dimensions() != (size, size) {
+                return Err(TextureError::WrongSize {
+                    path,
+                    expected: (size, size),
+                    found: image.dimensions(),
+                });
+            }
+
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            layer_indices.insert(name, layers.len() as u32);
+            layers.push(mip_chain(image));
+        }
+
+        Ok(Self {
+            layers,
+            size: size.unwrap(),
+            layer_indices,
+        })
+    }
+
+    /// Returns the width and height of every layer, in pixels, at the first mip level.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns the number of layers.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Returns the number of mip levels of every layer.
+    pub fn mip_level_count(&self) -> u32 {
+        self.layers[0].len() as u32
+    }
+
+    /// Returns the mip levels of a layer, from largest to smallest.
+    ///
+    /// N.B. This function will panic if `layer` is out of bounds.
+    pub fn mips(&self, layer: u32) -> &[image::RgbaImage] {
+        &self.layers[layer as usize]
+    }
+
+    /// Looks up the layer index of a texture by name.
+    pub fn layer(&self, name: &str) -> Result<u32, TextureError> {
+        self.layer_indices.get(name).copied().ok_or_else(|| TextureError::MissingTexture(name.to_owned()))
+    }
+
+    /// Returns the name and layer index of every texture.
+    pub fn layers(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.layer_indices.iter().map(|(name, &layer)| (name.as_str(), layer))
+    }
+}
+
+/// Builds the full chain of mip levels for an image, halving it until it is 1x1.
+fn mip_chain(image: image::RgbaImage) -> Vec<image::RgbaImage> {
+    let mut mips = vec![image];
+    loop {
+        let last = mips.last().unwrap();
+        if last.width() == 1 && last.height() == 1 {
+            return mips;
+        }
+        let next = downsample(last);
+        mips.push(next);
+    }
+}
+
+/// Halve an image by averaging each 2x2 block of pixels. The textures are sRGB, so colors are
+/// averaged in linear space to keep the smaller mips from darkening.
+fn downsample(image: &image::RgbaImage) -> image::RgbaImage {
+    let (width, height) = image.dimensions();
+    image::RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0.0f32; 4];
+        for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            // Odd sizes leave a last row or column which is folded into its neighbor.
+            let pixel = image.get_pixel((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+            for c in 0..3 {
+                sum[c] += srgb_to_linear(pixel[c]);
+            }
+            sum[3] += pixel[3] as f32 / 255.0;
+        }
+
+        image::Rgba([
+            linear_to_srgb(sum[0] / 4.0),
+            linear_to_srgb(sum[1] / 4.0),
+            linear_to_srgb(sum[2] / 4.0),
+            (sum[3] / 4.0 * 255.0).round() as u8,
+        ])
+    })
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
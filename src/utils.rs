@@ -38,6 +38,36 @@ impl From<cgmath::Matrix4<f32>> for PaddedMatrix4 {
     }
 }
 
+/// The camera's position and the inverse of its view projection matrix, for shaders which need to
+/// work out where a fragment lies relative to the camera.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ViewUniforms {
+    inverse_matrix: [[f32; 4]; 4],
+    /// The fourth component is unused.
+    position: [f32; 4],
+}
+
+unsafe impl Pod for ViewUniforms {}
+unsafe impl Zeroable for ViewUniforms {}
+
+impl ViewUniforms {
+    pub fn new(matrix: cgmath::Matrix4<f32>, position: cgmath::Point3<f32>) -> Self {
+        use cgmath::SquareMatrix;
+
+        ViewUniforms {
+            inverse_matrix: matrix.invert().expect("camera matrix is not invertible").into(),
+            position: [position.x, position.y, position.z, 1.0],
+        }
+    }
+}
+
+impl AsRef<[f32]> for ViewUniforms {
+    fn as_ref(&self) -> &[f32] {
+        bytemuck::cast_slice(std::slice::from_ref(self))
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Vertex {
@@ -66,17 +96,27 @@ unsafe impl Zeroable for Vertex {}
 pub struct IVertex {
     v_pos: [i32; 3],
     b_pos: [i32; 3],
+    /// Texture coordinates in blocks, repeating once per block across the texture array layer
+    /// given by `data`.
     tc: [f32; 2],
+    /// The face, the texture array layer, and the light level. The last byte is unused.
     data: [u8; 4],
 }
 
 impl IVertex {
-    pub fn new(v_pos: cgmath::Vector3<i32>, b_pos: cgmath::Point3<i32>, tc: [f32; 2], face: u8) -> IVertex {
+    pub fn new(
+        v_pos: cgmath::Vector3<i32>,
+        b_pos: cgmath::Point3<i32>,
+        tc: [f32; 2],
+        face: u8,
+        layer: u8,
+        light: u8,
+    ) -> IVertex {
         IVertex {
             v_pos: v_pos.into(),
             b_pos: b_pos.into(),
             tc,
-            data: [face, 0, 0, 0],
+            data: [face, layer, light, 0],
         }
     }
 }
@@ -86,9 +126,110 @@ pub const IVERTEX_SIZE: usize = std::mem::size_of::<IVertex>();
 unsafe impl Pod for IVertex {}
 unsafe impl Zeroable for IVertex {}
 
-pub fn load_image_bytes(path: &str) -> Vec<u8> {
-    let image = image::open(path).unwrap();
-    image.to_rgba().into_raw()
+impl crate::mesh::MeshVertex for IVertex {
+    const ATTRIBUTES: &'static [wgpu::VertexAttributeDescriptor] = &[
+        wgpu::VertexAttributeDescriptor {
+            format: wgpu::VertexFormat::Int3,
+            offset: 0,
+            shader_location: 0,
+        },
+        wgpu::VertexAttributeDescriptor {
+            format: wgpu::VertexFormat::Int3,
+            offset: 4*3,
+            shader_location: 1,
+        },
+        wgpu::VertexAttributeDescriptor {
+            format: wgpu::VertexFormat::Float2,
+            offset: 4*3 + 4*3,
+            shader_location: 2,
+        },
+        wgpu::VertexAttributeDescriptor {
+            format: wgpu::VertexFormat::Uint,
+            offset: 4*3 + 4*3 + 4*2,
+            shader_location: 3,
+        },
+    ];
+    const VERTEX_SHADER: &'static [u8] = include_bytes!("../shaders/shader.vert.spv");
+
+    fn new(
+        v_pos: cgmath::Vector3<i32>,
+        b_pos: cgmath::Point3<i32>,
+        tc: [f32; 2],
+        face: crate::mesh::CuboidFaces,
+        _corner: u8,
+        layer: u8,
+        light: u8,
+    ) -> Self {
+        IVertex::new(v_pos, b_pos, tc, face.bits(), layer, light)
+    }
+}
+
+/// A voxel vertex packed into two words, a quarter of the size of an `IVertex`. This only works
+/// for chunk-local geometry, since positions and texture coordinates are limited to 0..=31.
+///
+/// The first word holds the position (five bits per axis, from bit 0), the index of the face in
+/// `CuboidFaces::ALL` (three bits, from bit 15), the corner of the face (two bits, from bit 18),
+/// and the texture coordinates (five bits each, from bit 20). The second word holds the texture
+/// array layer (bits 0-7) and the light level (bits 8-15).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PackedVertex {
+    data: [u32; 2],
+}
+
+impl PackedVertex {
+    pub fn new(
+        v_pos: cgmath::Vector3<i32>,
+        tc: [u32; 2],
+        face: u32,
+        corner: u32,
+        layer: u8,
+        light: u8,
+    ) -> PackedVertex {
+        debug_assert!(v_pos.x >= 0 && v_pos.x < 32 && v_pos.y >= 0 && v_pos.y < 32 && v_pos.z >= 0 && v_pos.z < 32);
+        debug_assert!(tc[0] < 32 && tc[1] < 32 && face < 8 && corner < 4);
+
+        let position = v_pos.x as u32 | (v_pos.y as u32) << 5 | (v_pos.z as u32) << 10;
+        PackedVertex {
+            data: [
+                position | face << 15 | corner << 18 | tc[0] << 20 | tc[1] << 25,
+                layer as u32 | (light as u32) << 8,
+            ],
+        }
+    }
+}
+
+unsafe impl Pod for PackedVertex {}
+unsafe impl Zeroable for PackedVertex {}
+
+impl crate::mesh::MeshVertex for PackedVertex {
+    const ATTRIBUTES: &'static [wgpu::VertexAttributeDescriptor] = &[
+        wgpu::VertexAttributeDescriptor {
+            format: wgpu::VertexFormat::Uint2,
+            offset: 0,
+            shader_location: 0,
+        },
+    ];
+    const VERTEX_SHADER: &'static [u8] = include_bytes!("../shaders/shader_packed.vert.spv");
+
+    fn new(
+        v_pos: cgmath::Vector3<i32>,
+        _b_pos: cgmath::Point3<i32>,
+        tc: [f32; 2],
+        face: crate::mesh::CuboidFaces,
+        corner: u8,
+        layer: u8,
+        light: u8,
+    ) -> Self {
+        PackedVertex::new(
+            v_pos,
+            [tc[0] as u32, tc[1] as u32],
+            face.index() as u32,
+            corner as u32,
+            layer,
+            light,
+        )
+    }
 }
 
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
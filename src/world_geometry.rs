@@ -1,173 +1,1043 @@
 use cgmath::{Matrix4, Point3, Vector3};
 
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::allocator::{AllocatorStats, RangeAllocator};
+use crate::block::{BlockId, BlockRegistry};
+use crate::chunk::{CHUNK_DIM, Chunk, ChunkNeighborhood};
+use crate::chunk_worker::{ChunkJob, ChunkResult, ChunkWorkerPool};
 use crate::simplex;
-use crate::mesh::{CuboidFaces, IMeshAccumulator};
+use crate::mesh::{self, CuboidFaces, IMeshAccumulator};
 use crate::utils;
 
+/// The number of worker threads generating and meshing chunks.
+const WORKER_COUNT: usize = 4;
+
+/// The most finished chunks which will be uploaded in a single frame. Anything beyond this waits
+/// for the next frame, so that a burst of finished chunks doesn't stall the render thread.
+const UPLOAD_BUDGET: usize = 64;
+
+/// Room for this many extra faces is left after each chunk's mesh in the shared buffers. A chunk
+/// which is remeshed after an edit can be written back in place as long as its new mesh fits, rather
+/// than moving to a new region of the buffers.
+const EDIT_HEADROOM_FACES: usize = 32;
+
+/// The initial sizes of the shared buffers, in vertices, indices and chunk transforms. The buffers
+/// grow as more space is needed.
+const INITIAL_VERTEX_CAPACITY: usize = 1 << 16;
+const INITIAL_INDEX_CAPACITY: usize = 1 << 17;
+const INITIAL_TRANSFORM_CAPACITY: usize = 256;
+
 pub struct ChunkIndex {
+    pub position: Point3<i32>,
     pub vertex_offset: usize,
     pub index_offset: usize,
     pub index_count: usize,
     pub transform_index: usize,
+    /// The space reserved for this chunk in the shared buffers, which may be more than its mesh is
+    /// currently using.
+    pub vertex_capacity: usize,
+    pub index_capacity: usize,
+}
+
+impl ChunkIndex {
+    /// Returns the minimum and maximum corners of the chunk's bounding box in world space.
+    pub fn bounds(&self) -> (Point3<f32>, Point3<f32>) {
+        let dim = CHUNK_DIM as f32;
+        let min = Point3::new(self.position.x as f32 * dim, self.position.y as f32 * dim, self.position.z as f32 * dim);
+        (min, min + Vector3::new(dim, dim, dim))
+    }
 }
 
+/// How much of each of the shared buffers owned by a `WorldGeometryManager` is in use.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct GeometryBufferStats {
+    pub vertices: AllocatorStats,
+    pub indices: AllocatorStats,
+    pub transforms: AllocatorStats,
+}
+
+/// The mesh built for a single chunk, kept on the host so that the shared GPU buffers can be
+/// defragmented without remeshing every chunk.
+struct ChunkMesh {
+    vertices: Vec<Vertex>,
+    indices: Vec<Index>,
+}
+
+type TransformsBuf = crate::managed_buffer::ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>;
+type VertexBuf = crate::managed_buffer::ManagedBuffer<Vertex, Vec<Vertex>>;
+type IndexBuf = crate::managed_buffer::ManagedBuffer<Index, Vec<Index>>;
+
+/// The type of the indices in chunk meshes. The render pipeline's index format follows this.
+pub type Index = u32;
+
+/// The vertex format of chunk meshes. The render pipeline's vertex layout and vertex shader follow
+/// this, so `utils::IVertex` can be swapped in here when the unpacked data is easier to debug.
+pub type Vertex = utils::PackedVertex;
+
 #[allow(dead_code)]
 pub struct WorldGeometryManager {
-    pub chunks: Vec<ChunkIndex>,
+    /// The region of the shared buffers claimed by each chunk with a non-empty mesh.
+    pub chunks: HashMap<Point3<i32>, ChunkIndex>,
+
+    /// The voxel data for every loaded chunk, keyed by chunk coordinates. Chunks are shared with the
+    /// worker pool while their neighborhoods are being meshed.
+    pub chunk_data: HashMap<Point3<i32>, Arc<Chunk>>,
+    chunk_meshes: HashMap<Point3<i32>, ChunkMesh>,
+    /// The version of the most recently requested mesh for each chunk. A chunk without an entry has
+    /// not been meshed against its current neighbors, and meshes which come back from the worker
+    /// pool with an older version are thrown away.
+    mesh_versions: HashMap<Point3<i32>, u32>,
+    next_mesh_version: u32,
+    /// The algorithm used to build chunk meshes.
+    mesher: Mesher,
+
+    /// Chunks which have been handed to the worker pool for generation, and have not come back yet.
+    pending: HashSet<Point3<i32>>,
+    workers: ChunkWorkerPool,
+
+    /// Describes every type of block which may appear in `chunk_data`.
+    pub block_registry: Arc<BlockRegistry>,
 
     /// This buffer holds the transforms for each of our chunks.
-    pub transforms_buf: crate::managed_buffer::ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+    pub transforms_buf: TransformsBuf,
 
     /// Chunks are cubes of world geometry, and this value is the size of the cube.
     chunk_dim: usize,
-    noise: simplex::Simplex,
+    noise: Arc<simplex::Simplex>,
+
+    /// The chunk which the loaded region is centered around.
+    center: Point3<i32>,
+    /// Chunks within this many chunks of `center` horizontally are kept loaded.
+    view_radius: i32,
+    /// Chunks within this many chunks of `center` vertically are kept loaded.
+    view_radius_z: i32,
 
     /// This buffer holds the full mesh for the world geometry.
-    pub vertex_buf: crate::managed_buffer::ManagedBuffer<utils::IVertex, Vec<utils::IVertex>>,
-    pub index_buf: crate::managed_buffer::ManagedBuffer<u16, Vec<u16>>,
+    pub vertex_buf: VertexBuf,
+    pub index_buf: IndexBuf,
+
+    /// These hand out the regions of the shared buffers claimed by each chunk.
+    vertex_alloc: RangeAllocator,
+    index_alloc: RangeAllocator,
+    transform_alloc: RangeAllocator,
 }
 
 impl WorldGeometryManager {
     // TODO: This shouldn't take a GpuContext. This needs another layer of abstraction around memory
     // management.
-    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Option<Self> {
-        let chunk_dim = 16;
-        let mut chunks = Vec::with_capacity(100);
-        let mut chunk_transforms: Vec<utils::PaddedMatrix4> = Vec::with_capacity(100);
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-        let noise = crate::simplex::Simplex::with_seed(0);
-
-        for y in 0..30 {
-            for x in 0..30 {
-                // Generate our transform matrix for this chunk.
-                let t = Matrix4::from_translation(Vector3::new((x * chunk_dim as i32) as f32, (y * chunk_dim as i32) as f32, 0.0));
-
-                // Now generate the actual mesh for the chunk.
-                let (vertices_n, indices_n) = generate_chunk_x_y(x, y, chunk_dim, &noise).report();
-                let chunk_index = ChunkIndex {
-                    vertex_offset: vertices.len(),
-                    index_offset: indices.len(),
-                    index_count: indices_n.len(),
-                    transform_index: chunk_transforms.len(),
-                };
-
-                // And update our local accumulators.
-                vertices.extend(vertices_n);
-                indices.extend(indices_n);
-                chunk_transforms.push(t.into());
-                chunks.push(chunk_index);
-            }
-        }
+    /// Create a new `WorldGeometryManager` for a world made of the blocks in `block_registry`,
+    /// requesting every chunk within `view_radius` chunks of `position` horizontally and
+    /// `view_radius_z` chunks vertically. Chunks are generated and meshed in the background, and
+    /// appear as `update` is called.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        block_registry: BlockRegistry,
+        position: Point3<f32>,
+        view_radius: i32,
+        view_radius_z: i32,
+    ) -> Result<Self, crate::managed_buffer::ManagedBufferError> {
+        let noise = Arc::new(crate::simplex::Simplex::with_seed(0));
+        let block_registry = Arc::new(block_registry);
+        let workers = ChunkWorkerPool::new(WORKER_COUNT, noise.clone(), block_registry.clone());
+
+        let chunk_data = HashMap::new();
+        let chunk_meshes = HashMap::new();
 
         // Create the transforms buffer holding the transforms for each chunk.
         let transforms_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
             gpu_context,
-            chunk_transforms,
-        ).ok()?;
+            vec![bytemuck::Zeroable::zeroed(); INITIAL_TRANSFORM_CAPACITY],
+        )?;
 
-        // Now we create the vertex buffer and index buffer on the GPU.
+        // Now we create the vertex buffer and index buffer on the GPU. Chunks claim regions of
+        // these as their meshes arrive.
         let vertex_buf = crate::managed_buffer::ManagedBuffer::new_vertex_buf_with_data(
             gpu_context,
-            vertices,
-        ).ok()?;
+            vec![bytemuck::Zeroable::zeroed(); INITIAL_VERTEX_CAPACITY],
+        )?;
         let index_buf = crate::managed_buffer::ManagedBuffer::new_index_buf_with_data(
             gpu_context,
-            indices,
-        ).ok()?;
-
-        // create the vertex and index buffers
-        // create the chunk transforms buffer
-        // create some initial chunks, centered around 0, 0
-        Some(Self {
-            chunks,
+            vec![0; INITIAL_INDEX_CAPACITY],
+        )?;
+
+        // Chunks are meshed against their neighbors, so generate every neighbor of every chunk
+        // within the view radius.
+        let center = chunk_of(position);
+        let (load_radius, load_radius_z) = load_radius(view_radius, view_radius_z);
+        let pending: HashSet<_> = chunks_within_box(center, load_radius, load_radius_z).collect();
+        workers.request(center, pending.iter().copied().map(ChunkJob::Generate));
+
+        Ok(Self {
+            chunks: HashMap::new(),
+            chunk_data,
+            chunk_meshes,
+            mesh_versions: HashMap::new(),
+            next_mesh_version: 0,
+            mesher: Mesher::Greedy,
+            pending,
+            workers,
+            block_registry,
             transforms_buf,
-            chunk_dim,
+            chunk_dim: CHUNK_DIM,
             noise,
+            center,
+            view_radius,
+            view_radius_z,
             vertex_buf,
             index_buf,
+            vertex_alloc: RangeAllocator::new(INITIAL_VERTEX_CAPACITY),
+            index_alloc: RangeAllocator::new(INITIAL_INDEX_CAPACITY),
+            transform_alloc: RangeAllocator::new(INITIAL_TRANSFORM_CAPACITY),
         })
     }
+
+    /// Stream chunks in and out around `position`. Chunks which come within the view radius are
+    /// requested from the worker pool, and chunks which have fallen well outside of it are dropped.
+    /// Up to `UPLOAD_BUDGET` finished jobs are then picked up from the pool.
+    ///
+    /// A chunk is only meshed once every chunk around it has been generated, so that the faces on
+    /// its border can be checked against its neighbors. For this reason chunks are generated in a
+    /// box reaching one chunk further out than the view radius, which holds every neighbor of every
+    /// chunk within it.
+    ///
+    /// Each chunk claims its own region of the shared GPU buffers, which is released when the chunk
+    /// is unloaded. The buffers grow when they run out of space, which reallocates them; any bind
+    /// groups referencing `transforms_buf` must be rebuilt whenever its generation changes.
+    pub fn update(&mut self, gpu_context: &crate::gpu::GpuContext, position: Point3<f32>) {
+        let (load_radius, load_radius_z) = load_radius(self.view_radius, self.view_radius_z);
+        // Chunks are only unloaded once they are a chunk beyond the loaded box, so that moving
+        // back and forth over a chunk border does not repeatedly load and unload the same chunks.
+        let (unload_radius, unload_radius_z) = (load_radius + 1, load_radius_z + 1);
+
+        let center = chunk_of(position);
+        if center != self.center {
+            self.center = center;
+
+            let unloaded: Vec<_> = self.chunks
+                .keys()
+                .copied()
+                .filter(|&pos| !within_box(center, pos, unload_radius, unload_radius_z))
+                .collect();
+            for pos in unloaded {
+                self.release(pos);
+            }
+            self.chunk_data.retain(|&pos, _| within_box(center, pos, unload_radius, unload_radius_z));
+            self.chunk_meshes.retain(|&pos, _| within_box(center, pos, unload_radius, unload_radius_z));
+            self.mesh_versions.retain(|&pos, _| within_box(center, pos, unload_radius, unload_radius_z));
+
+            // Forget about queued jobs we no longer want.
+            for job in self.workers.cancel(|pos| !within_box(center, pos, unload_radius, unload_radius_z)) {
+                if let ChunkJob::Generate(pos) = job {
+                    self.pending.remove(&pos);
+                }
+            }
+
+            let mut requests = Vec::new();
+            for pos in chunks_within_box(center, load_radius, load_radius_z) {
+                if !self.chunk_data.contains_key(&pos) && self.pending.insert(pos) {
+                    requests.push(ChunkJob::Generate(pos));
+                }
+            }
+            // Chunks which were already loaded may have just come within the view radius.
+            for pos in chunks_within(center, self.view_radius, self.view_radius_z) {
+                self.request_mesh(pos, &mut requests);
+            }
+            self.workers.request(center, requests);
+        }
+
+        let mut requests = Vec::new();
+        for _ in 0..UPLOAD_BUDGET {
+            match self.workers.try_recv() {
+                Some(ChunkResult::Generated { position, chunk }) => {
+                    self.pending.remove(&position);
+
+                    // The camera may have moved away while this chunk was being worked on.
+                    if !within_box(center, position, unload_radius, unload_radius_z) {
+                        continue;
+                    }
+                    self.chunk_data.insert(position, Arc::new(chunk));
+
+                    // This chunk may have completed its own neighborhood, or that of any chunk
+                    // around it.
+                    for neighbor in neighborhood_of(position) {
+                        self.request_mesh(neighbor, &mut requests);
+                    }
+                },
+                Some(ChunkResult::Meshed { position, version, vertices, indices }) => {
+                    // The chunk may have been unloaded or changed again while this mesh was being
+                    // built.
+                    if self.mesh_versions.get(&position) != Some(&version) {
+                        continue;
+                    }
+
+                    self.upload_mesh(gpu_context, position, ChunkMesh { vertices, indices });
+                },
+                None => break,
+            }
+        }
+        self.workers.request(center, requests);
+    }
+
+    /// Returns true while any chunk within the view radius is still waiting to be generated or
+    /// meshed for the first time.
+    pub fn is_loading(&self) -> bool {
+        !self.pending.is_empty()
+            || chunks_within(self.center, self.view_radius, self.view_radius_z)
+                .any(|pos| !self.chunk_meshes.contains_key(&pos))
+    }
+
+    /// Returns the horizontal and vertical radii, in chunks, of the region kept loaded around the
+    /// camera.
+    #[allow(dead_code)]
+    pub fn view_radius(&self) -> (i32, i32) {
+        (self.view_radius, self.view_radius_z)
+    }
+
+    /// Sets the horizontal and vertical radii, in chunks, of the region kept loaded around the
+    /// camera. This takes effect the next time the camera moves into a new chunk.
+    #[allow(dead_code)]
+    pub fn set_view_radius(&mut self, view_radius: i32, view_radius_z: i32) {
+        self.view_radius = view_radius.max(0);
+        self.view_radius_z = view_radius_z.max(0);
+    }
+
+    /// Returns the algorithm used to build chunk meshes.
+    #[allow(dead_code)]
+    pub fn mesher(&self) -> Mesher {
+        self.mesher
+    }
+
+    /// Sets the algorithm used to build chunk meshes, meshing every loaded chunk again with it.
+    #[allow(dead_code)]
+    pub fn set_mesher(&mut self, mesher: Mesher) {
+        if mesher == self.mesher {
+            return;
+        }
+        self.mesher = mesher;
+
+        let mut requests = Vec::new();
+        let meshed: Vec<_> = self.mesh_versions.drain().map(|(pos, _)| pos).collect();
+        for pos in meshed {
+            self.request_mesh(pos, &mut requests);
+        }
+        self.workers.request(self.center, requests);
+    }
+
+    /// Mesh the chunk at `pos` again, along with every loaded chunk around it. This must be called
+    /// whenever a chunk's voxel data changes wholesale, since the faces on both sides of each of its
+    /// borders may have been exposed or hidden.
+    #[allow(dead_code)]
+    pub fn chunk_changed(&mut self, pos: Point3<i32>) {
+        let mut requests = Vec::new();
+        for neighbor in neighborhood_of(pos) {
+            self.remesh(neighbor, &mut requests);
+        }
+        self.workers.request(self.center, requests);
+    }
+
+    /// Returns the block at the given world position, or `None` if the chunk containing that
+    /// position has not been generated.
+    #[allow(dead_code)]
+    pub fn get_block(&self, pos: Point3<i32>) -> Option<BlockId> {
+        let (chunk_pos, local_pos) = split_block_pos(pos);
+        self.chunk_data.get(&chunk_pos)?.get(local_pos)
+    }
+
+    /// Sets the block at the given world position, returning the block that was there before. If
+    /// the chunk containing that position has not been generated, nothing is written and `None` is
+    /// returned.
+    ///
+    /// The chunk is queued to be meshed again, along with any chunks sharing the border the block
+    /// lies on. The new meshes appear as `update` is called.
+    #[allow(dead_code)]
+    pub fn set_block(&mut self, pos: Point3<i32>, block: BlockId) -> Option<BlockId> {
+        let (chunk_pos, local_pos) = split_block_pos(pos);
+
+        // Workers may still be meshing from the old chunk data, so copy it if it is shared.
+        let chunk = Arc::make_mut(self.chunk_data.get_mut(&chunk_pos)?);
+        let previous = chunk.set(local_pos, block)?;
+        if previous == block {
+            return Some(previous);
+        }
+
+        let mut requests = Vec::new();
+        for chunk_pos in chunks_touching_block(pos) {
+            self.remesh(chunk_pos, &mut requests);
+        }
+        self.workers.request(self.center, requests);
+
+        Some(previous)
+    }
+
+    /// Throw away the mesh version of the chunk at `pos` and request a new mesh for it, so that any
+    /// mesh still being built from stale data is ignored. Chunks which have never been meshed are
+    /// left alone; they will be meshed from the current data once they come into view.
+    fn remesh(&mut self, pos: Point3<i32>, requests: &mut Vec<ChunkJob>) {
+        if self.mesh_versions.remove(&pos).is_none() {
+            return;
+        }
+        self.request_mesh(pos, requests);
+
+        // The chunk may have left the view radius since it was meshed, in which case no new mesh
+        // was requested and the old one is now stale.
+        if !self.mesh_versions.contains_key(&pos) {
+            self.chunk_meshes.remove(&pos);
+            self.release(pos);
+        }
+    }
+
+    /// Returns how much of each of the shared buffers is in use.
+    #[allow(dead_code)]
+    pub fn buffer_stats(&self) -> GeometryBufferStats {
+        GeometryBufferStats {
+            vertices: self.vertex_alloc.stats(),
+            indices: self.index_alloc.stats(),
+            transforms: self.transform_alloc.stats(),
+        }
+    }
+
+    /// Upload the mesh of the chunk at `pos` into the shared buffers. The mesh is written over the
+    /// chunk's current region if it fits, and moved to a new region otherwise.
+    fn upload_mesh(&mut self, gpu_context: &crate::gpu::GpuContext, pos: Point3<i32>, mesh: ChunkMesh) {
+        // Chunks without any faces are never drawn, so they don't need a region.
+        if mesh.indices.is_empty() {
+            self.release(pos);
+            self.chunk_meshes.insert(pos, mesh);
+            return;
+        }
+
+        let fits = self.chunks.get(&pos).map_or(false, |chunk| {
+            mesh.vertices.len() <= chunk.vertex_capacity && mesh.indices.len() <= chunk.index_capacity
+        });
+        if !fits {
+            self.allocate_region(gpu_context, pos, mesh.vertices.len(), mesh.indices.len());
+        }
+
+        let chunk = self.chunks.get_mut(&pos).unwrap();
+        self.vertex_buf.write_slice(chunk.vertex_offset, &mesh.vertices);
+        self.index_buf.write_slice(chunk.index_offset, &mesh.indices);
+        chunk.index_count = mesh.indices.len();
+        self.chunk_meshes.insert(pos, mesh);
+    }
+
+    /// Claim a new region of the shared buffers for the chunk at `pos`, with room for a mesh of the
+    /// given size, freeing its old region. A chunk keeps its transform if it already had one.
+    fn allocate_region(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        pos: Point3<i32>,
+        vertex_count: usize,
+        index_count: usize,
+    ) {
+        // Leave some room after the mesh, so that small edits can be written back in place.
+        let vertex_capacity = vertex_count + 4 * EDIT_HEADROOM_FACES;
+        let index_capacity = index_count + 6 * EDIT_HEADROOM_FACES;
+
+        let transform_index = match self.chunks.remove(&pos) {
+            Some(old) => {
+                self.vertex_alloc.free(old.vertex_offset..old.vertex_offset + old.vertex_capacity);
+                self.index_alloc.free(old.index_offset..old.index_offset + old.index_capacity);
+                old.transform_index
+            },
+            None => {
+                let transform_index = allocate_growing(gpu_context, &mut self.transform_alloc, &mut self.transforms_buf, 1);
+                let dim = CHUNK_DIM as i32;
+                let t = Matrix4::from_translation(Vector3::new(
+                    (pos.x * dim) as f32,
+                    (pos.y * dim) as f32,
+                    (pos.z * dim) as f32,
+                ));
+                self.transforms_buf.write_slice(transform_index, &[t.into()]);
+                transform_index
+            },
+        };
+
+        // If there is enough free space in total, but it is scattered in holes too small for this
+        // chunk, compact the buffers rather than growing them.
+        let fragmented = |stats: AllocatorStats, size: usize| stats.largest_free < size && stats.free >= size;
+        if fragmented(self.vertex_alloc.stats(), vertex_capacity) || fragmented(self.index_alloc.stats(), index_capacity) {
+            self.defragment();
+        }
+
+        let vertex_offset = allocate_growing(gpu_context, &mut self.vertex_alloc, &mut self.vertex_buf, vertex_capacity);
+        let index_offset = allocate_growing(gpu_context, &mut self.index_alloc, &mut self.index_buf, index_capacity);
+
+        self.chunks.insert(pos, ChunkIndex {
+            position: pos,
+            vertex_offset,
+            index_offset,
+            index_count: 0,
+            transform_index,
+            vertex_capacity,
+            index_capacity,
+        });
+    }
+
+    /// Free the regions of the shared buffers claimed by the chunk at `pos`, if it has any.
+    fn release(&mut self, pos: Point3<i32>) {
+        if let Some(chunk) = self.chunks.remove(&pos) {
+            self.vertex_alloc.free(chunk.vertex_offset..chunk.vertex_offset + chunk.vertex_capacity);
+            self.index_alloc.free(chunk.index_offset..chunk.index_offset + chunk.index_capacity);
+            self.transform_alloc.free(chunk.transform_index..chunk.transform_index + 1);
+        }
+    }
+
+    /// Move the regions of every chunk to the start of the vertex and index buffers, so that all of
+    /// their free space is left in a single range at the end. Every mesh is written again from
+    /// `chunk_meshes`.
+    fn defragment(&mut self) {
+        crate::benchmark!("chunk defragment", {
+            self.vertex_alloc.reset();
+            self.index_alloc.reset();
+
+            for (pos, chunk) in self.chunks.iter_mut() {
+                let mesh = &self.chunk_meshes[pos];
+                // The allocators were just emptied, and every region fit before, so these can't fail.
+                chunk.vertex_offset = self.vertex_alloc.allocate(chunk.vertex_capacity).unwrap().start;
+                chunk.index_offset = self.index_alloc.allocate(chunk.index_capacity).unwrap().start;
+                self.vertex_buf.write_slice(chunk.vertex_offset, &mesh.vertices);
+                self.index_buf.write_slice(chunk.index_offset, &mesh.indices);
+            }
+        });
+
+        log::info!(
+            "Defragmented world geometry: vertices {}, indices {}.",
+            self.vertex_alloc.stats(),
+            self.index_alloc.stats(),
+        );
+    }
+
+    /// Queue a job to mesh the chunk at `pos`, if it lies within the view radius, has not been
+    /// meshed yet, and its whole neighborhood has been generated. Chunks which are entirely air are
+    /// given an empty mesh right away.
+    fn request_mesh(&mut self, pos: Point3<i32>, requests: &mut Vec<ChunkJob>) {
+        if !within(self.center, pos, self.view_radius, self.view_radius_z) || self.mesh_versions.contains_key(&pos) {
+            return;
+        }
+
+        let chunk_data = &self.chunk_data;
+        let neighborhood = match ChunkNeighborhood::gather(pos, |p| chunk_data.get(&p).cloned()) {
+            Some(neighborhood) => neighborhood,
+            None => return,
+        };
+
+        let version = self.next_mesh_version;
+        self.next_mesh_version = self.next_mesh_version.wrapping_add(1);
+        self.mesh_versions.insert(pos, version);
+
+        if neighborhood.center().is_empty() {
+            self.release(pos);
+            self.chunk_meshes.insert(pos, ChunkMesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+            });
+        } else {
+            requests.push(ChunkJob::Mesh {
+                position: pos,
+                version,
+                neighborhood,
+                mesher: self.mesher,
+            });
+        }
+    }
 }
 
-/// Generate a chunk of world geometry given: coordinates, the chunk dimensions, and a simplex noise
-/// instance.
-pub fn generate_chunk_x_y(
-    x_off: i32,
-    y_off: i32,
-    chunk_dim: usize,
-    noise: &simplex::Simplex,
-) -> IMeshAccumulator {
+/// Allocate `size` elements of `buf` with `allocator`, growing both if there is no free range large
+/// enough. Returns the offset of the allocation.
+fn allocate_growing<T: bytemuck::Pod + bytemuck::Zeroable>(
+    gpu_context: &crate::gpu::GpuContext,
+    allocator: &mut RangeAllocator,
+    buf: &mut crate::managed_buffer::ManagedBuffer<T, Vec<T>>,
+    size: usize,
+) -> usize {
+    if let Some(range) = allocator.allocate(size) {
+        return range.start;
+    }
+
+    let capacity = (allocator.capacity() * 2).max(allocator.capacity() + size);
+    log::info!("Growing world geometry buffer from {} to {} elements.", allocator.capacity(), capacity);
+    allocator.grow(capacity);
+    buf.resize(gpu_context, capacity);
+
+    allocator.allocate(size).expect("allocation failed after growing the buffer").start
+}
+
+/// Splits the world position of a block into the chunk containing it, and its position within that
+/// chunk.
+fn split_block_pos(pos: Point3<i32>) -> (Point3<i32>, Point3<i32>) {
+    let dim = CHUNK_DIM as i32;
+    (
+        Point3::new(pos.x.div_euclid(dim), pos.y.div_euclid(dim), pos.z.div_euclid(dim)),
+        Point3::new(pos.x.rem_euclid(dim), pos.y.rem_euclid(dim), pos.z.rem_euclid(dim)),
+    )
+}
+
+/// Returns every chunk whose mesh depends on the block at the given world position. A block on the
+/// border of its chunk is visible to the mesher of the chunk across that border, so this is the
+/// block's own chunk, along with every chunk touching the block.
+fn chunks_touching_block(pos: Point3<i32>) -> Vec<Point3<i32>> {
+    let (chunk_pos, local_pos) = split_block_pos(pos);
+    let last = CHUNK_DIM as i32 - 1;
+    let span = |local: i32| match local {
+        0 => -1..=0,
+        l if l == last => 0..=1,
+        _ => 0..=0,
+    };
+
+    let mut chunks = Vec::new();
+    for dx in span(local_pos.x) {
+        for dy in span(local_pos.y) {
+            for dz in span(local_pos.z) {
+                chunks.push(chunk_pos + Vector3::new(dx, dy, dz));
+            }
+        }
+    }
+    chunks
+}
+
+/// Returns the chunk containing a world position.
+fn chunk_of(position: Point3<f32>) -> Point3<i32> {
+    let dim = CHUNK_DIM as f32;
+    Point3::new(
+        (position.x / dim).floor() as i32,
+        (position.y / dim).floor() as i32,
+        (position.z / dim).floor() as i32,
+    )
+}
+
+/// Returns the chunk at `pos` followed by the 26 chunks surrounding it.
+fn neighborhood_of(pos: Point3<i32>) -> impl Iterator<Item = Point3<i32>> {
+    std::iter::once(pos).chain(
+        (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| Vector3::new(dx, dy, dz))))
+            .filter(|&offset| offset != Vector3::new(0, 0, 0))
+            .map(move |offset| pos + offset),
+    )
+}
+
+/// Returns true if `pos` lies within a vertical cylinder of chunks around `center`, with the given
+/// horizontal and vertical radii.
+fn within(center: Point3<i32>, pos: Point3<i32>, radius: i32, radius_z: i32) -> bool {
+    let d = pos - center;
+    d.x * d.x + d.y * d.y <= radius * radius && d.z.abs() <= radius_z
+}
+
+/// Returns every chunk within `radius` chunks of `center` horizontally and `radius_z` chunks
+/// vertically.
+fn chunks_within(center: Point3<i32>, radius: i32, radius_z: i32) -> impl Iterator<Item = Point3<i32>> {
+    chunks_within_box(center, radius, radius_z).filter(move |&pos| within(center, pos, radius, radius_z))
+}
+
+/// Returns true if `pos` lies within a box of chunks around `center`, reaching `radius` chunks out
+/// along the x- and y-axes and `radius_z` chunks along the z-axis. Unlike the cylinder of `within`,
+/// the box one chunk larger than a region holds every neighbor of every chunk in the region.
+fn within_box(center: Point3<i32>, pos: Point3<i32>, radius: i32, radius_z: i32) -> bool {
+    let d = pos - center;
+    d.x.abs() <= radius && d.y.abs() <= radius && d.z.abs() <= radius_z
+}
+
+/// Returns the horizontal and vertical radii of the box of chunks which has to be generated to mesh
+/// every chunk within the given view radii.
+fn load_radius(view_radius: i32, view_radius_z: i32) -> (i32, i32) {
+    (view_radius + 1, view_radius_z + 1)
+}
+
+/// Returns every chunk in the box `within_box` describes.
+fn chunks_within_box(center: Point3<i32>, radius: i32, radius_z: i32) -> impl Iterator<Item = Point3<i32>> {
+    (-radius_z..=radius_z)
+        .flat_map(move |dz| (-radius..=radius).map(move |dy| (dy, dz)))
+        .flat_map(move |(dy, dz)| (-radius..=radius).map(move |dx| center + Vector3::new(dx, dy, dz)))
+}
+
+/// The algorithms available for turning voxel data into a mesh.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mesher {
+    /// Emit a quad for every exposed face of every block. This is the simplest to reason about, and
+    /// is kept around to check the greedy mesher against.
+    Naive,
+    /// Merge exposed faces which share a plane and a block type into larger rectangles.
+    Greedy,
+}
+
+/// Build the mesh for the center chunk of a neighborhood with the chosen `mesher`.
+pub fn mesh_chunk(neighborhood: &ChunkNeighborhood, registry: &BlockRegistry, mesher: Mesher) -> IMeshAccumulator<Index, Vertex> {
+    match mesher {
+        Mesher::Naive => mesh_chunk_naive(neighborhood, registry),
+        Mesher::Greedy => mesh_chunk_greedy(neighborhood, registry),
+    }
+}
+
+/// Returns the block at the chunk-local position if it is solid, and the face on the given side of
+/// it is not hidden by an opaque neighbor.
+#[inline(always)]
+fn exposed_face(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    pos: Point3<i32>,
+    face: CuboidFaces,
+) -> Option<BlockId> {
+    let block = neighborhood.get(pos)?;
+    if !registry.get(block).solid {
+        return None;
+    }
+
+    let hidden = neighborhood
+        .get(pos + face.normal())
+        .map_or(false, |neighbor| registry.get(neighbor).is_opaque());
+    if hidden { None } else { Some(block) }
+}
+
+/// Returns the ambient occlusion of each corner of a block's face, in the order of
+/// `CuboidFaces::corners`; see `mesh::vertex_ao`. The blocks around each corner are looked up in
+/// the layer in front of the face, so corners on the border of the chunk are occluded by the
+/// blocks of the adjacent chunks.
+pub fn face_ao(
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    pos: Point3<i32>,
+    face: CuboidFaces,
+) -> [u8; 4] {
+    let normal = face.normal();
+    let front = pos + normal;
+    let opaque = |pos: Point3<i32>| neighborhood.get(pos).map_or(false, |block| registry.get(block).is_opaque());
+
+    let mut ao = [0; 4];
+    for (ao, corner) in ao.iter_mut().zip(face.corners().iter()) {
+        // Step from the block in front of the face toward the corner, along each of the two axes
+        // spanning the face.
+        let toward = corner.map(|c| 2 * c - 1);
+        let (side1, side2) = if normal.x != 0 {
+            (Vector3::new(0, toward.y, 0), Vector3::new(0, 0, toward.z))
+        } else if normal.y != 0 {
+            (Vector3::new(toward.x, 0, 0), Vector3::new(0, 0, toward.z))
+        } else {
+            (Vector3::new(toward.x, 0, 0), Vector3::new(0, toward.y, 0))
+        };
+        *ao = mesh::vertex_ao(opaque(front + side1), opaque(front + side2), opaque(front + side1 + side2));
+    }
+
+    ao
+}
+
+/// Build the mesh for the center chunk of a neighborhood, emitting only those faces which are not
+/// hidden by an opaque neighbor. Faces on the border of the chunk are checked against the voxels of
+/// the adjacent chunks.
+fn mesh_chunk_naive(neighborhood: &ChunkNeighborhood, registry: &BlockRegistry) -> IMeshAccumulator<Index, Vertex> {
     let mut m = IMeshAccumulator::new();
 
-    // Generate the height map for our current chunk of terrain.
-    let mut height_map = Vec::with_capacity(chunk_dim * chunk_dim);
-    for x_i in 0..chunk_dim {
-        for y_i in 0..chunk_dim {
-            let x = x_i as f32;// * 2.0;
-            let y = y_i as f32;// * 2.0;
-            let z1 = (noise.get2d(
-                (x_off as f64 + (x / chunk_dim as f32) as f64) / 2.0,
-                (y_off as f64 + (y / chunk_dim as f32) as f64) / 2.0,
-            ) * 20.0 as f64) as f32;
-
-            let mult = (noise.get2d(
-                x_off as f64 + (x / chunk_dim as f32) as f64,
-                y_off as f64 + (y / chunk_dim as f32) as f64,
-            ) * 2.0 as f64) as f32;
-
-            let extremes = (noise.get2d(
-                (x_off as f64 + (x / chunk_dim as f32) as f64) / 10.0,
-                (y_off as f64 + (y / chunk_dim as f32) as f64) / 10.0,
-            ) * 10.0 as f64) as f32;
-
-            height_map.push((z1 * mult * extremes).max(-1.0) as i32);
-        }
-    }
-
-    // Generate a mesh from the heightmap.
-    for x_i in 0..chunk_dim {
-        for y_i in 0..chunk_dim {
-            let z = height_map[x_i * chunk_dim + y_i];
-            let mut faces = CuboidFaces::TOP;
-            let mut z_max_delta: i32 = 1;
-            if x_i == 0 || height_map[(x_i - 1) * chunk_dim + y_i] < z {
-                faces |= CuboidFaces::LEFT;
-                if x_i != 0 {
-                    z_max_delta = z_max_delta.max(z - (height_map[(x_i - 1) * chunk_dim + y_i]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
+    for x_i in 0..CHUNK_DIM as i32 {
+        for y_i in 0..CHUNK_DIM as i32 {
+            for z_i in 0..CHUNK_DIM as i32 {
+                let pos = Point3::new(x_i, y_i, z_i);
+                for &face in CuboidFaces::ALL.iter() {
+                    if let Some(block) = exposed_face(neighborhood, registry, pos, face) {
+                        let ao = face_ao(neighborhood, registry, pos, face);
+                        m.add_box_face(pos, Vector3::new(1, 1, 1), face, block, registry, ao);
+                    }
                 }
             }
-            if x_i == (chunk_dim - 1) || height_map[(x_i + 1) * chunk_dim + y_i] < z {
-                faces |= CuboidFaces::RIGHT;
-                if x_i != (chunk_dim - 1) {
-                    z_max_delta = z_max_delta.max(z - (height_map[(x_i + 1) * chunk_dim + y_i]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
+        }
+    }
+
+    m
+}
+
+/// Build the mesh for the center chunk of a neighborhood like `mesh_chunk_naive`, but merge exposed
+/// faces of the same block which lie in the same plane into rectangles.
+///
+/// Each face direction is handled one slice of the chunk at a time. The exposed faces of the slice
+/// are gathered into a mask, and rectangles are grown greedily out of the mask: first as far as
+/// possible along one axis, then along the other for as long as every face in the next row matches.
+///
+/// Faces only match if their ambient occlusion matches too. A face whose corners are occluded
+/// unevenly is never merged, since stretching it would stretch its shading along with it.
+fn mesh_chunk_greedy(neighborhood: &ChunkNeighborhood, registry: &BlockRegistry) -> IMeshAccumulator<Index, Vertex> {
+    let mut m = IMeshAccumulator::new();
+    let dim = CHUNK_DIM as i32;
+    let mut mask: [Option<(BlockId, [u8; 4])>; CHUNK_DIM * CHUNK_DIM] = [None; CHUNK_DIM * CHUNK_DIM];
+
+    for &face in CuboidFaces::ALL.iter() {
+        // The axis the face points along, followed by the two axes spanning the slice.
+        let normal = face.normal();
+        let d = if normal.x != 0 { 0 } else if normal.y != 0 { 1 } else { 2 };
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+
+        let to_pos = |slice: i32, i: i32, j: i32| {
+            let mut pos = [0; 3];
+            pos[d] = slice;
+            pos[u] = i;
+            pos[v] = j;
+            Point3::from(pos)
+        };
+
+        for slice in 0..dim {
+            for i in 0..dim {
+                for j in 0..dim {
+                    let pos = to_pos(slice, i, j);
+                    mask[(i * dim + j) as usize] = exposed_face(neighborhood, registry, pos, face)
+                        .map(|block| (block, face_ao(neighborhood, registry, pos, face)));
                 }
             }
-            if y_i == 0 || height_map[x_i * chunk_dim + (y_i - 1)] < z {
-                faces |= CuboidFaces::BACK;
-                if y_i != 0 {
-                    z_max_delta = z_max_delta.max(z - (height_map[x_i * chunk_dim + (y_i - 1)]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
+
+            for i in 0..dim {
+                let mut j = 0;
+                while j < dim {
+                    let (block, ao) = match mask[(i * dim + j) as usize] {
+                        Some(entry) => entry,
+                        None => {
+                            j += 1;
+                            continue;
+                        },
+                    };
+
+                    // Grow along v, then along u while the whole row matches.
+                    let entry = Some((block, ao));
+                    let mergeable = ao.iter().all(|&a| a == ao[0]);
+                    let mut height = 1;
+                    while mergeable && j + height < dim && mask[(i * dim + j + height) as usize] == entry {
+                        height += 1;
+                    }
+                    let mut width = 1;
+                    while mergeable
+                        && i + width < dim
+                        && (j..j + height).all(|k| mask[((i + width) * dim + k) as usize] == entry)
+                    {
+                        width += 1;
+                    }
+
+                    for di in 0..width {
+                        for dj in 0..height {
+                            mask[((i + di) * dim + j + dj) as usize] = None;
+                        }
+                    }
+
+                    let mut extent = [1; 3];
+                    extent[u] = width;
+                    extent[v] = height;
+                    m.add_box_face(to_pos(slice, i, j), Vector3::from(extent), face, block, registry, ao);
+
+                    j += height;
                 }
             }
-            if y_i == (chunk_dim - 1) || height_map[x_i * chunk_dim + (y_i + 1)] < z {
-                faces |= CuboidFaces::FRONT;
-                if y_i != (chunk_dim - 1) {
-                    z_max_delta = z_max_delta.max(z - (height_map[x_i * chunk_dim + (y_i + 1)]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
+        }
+    }
+
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DIRT, GRASS, SNOW};
+
+    /// Build the neighborhood of the chunk at the origin, holding the given blocks at world
+    /// positions. Every other block is air.
+    fn neighborhood(blocks: &[(Point3<i32>, BlockId)]) -> ChunkNeighborhood {
+        let mut chunks: HashMap<Point3<i32>, Chunk> = HashMap::new();
+        for &(pos, block) in blocks {
+            let (chunk, local) = split_block_pos(pos);
+            chunks.entry(chunk).or_insert_with(Chunk::new).set(local, block);
+        }
+        ChunkNeighborhood::gather(Point3::new(0, 0, 0), |pos| {
+            Some(Arc::new(chunks.get(&pos).cloned().unwrap_or_else(Chunk::new)))
+        })
+        .unwrap()
+    }
+
+    /// Returns a 16x16 plain of `block` at height `z`, filling the center chunk's layer.
+    fn plain(z: i32, block: impl Fn(i32, i32) -> BlockId) -> Vec<(Point3<i32>, BlockId)> {
+        let dim = CHUNK_DIM as i32;
+        (0..dim)
+            .flat_map(|x| (0..dim).map(move |y| (x, y)))
+            .map(|(x, y)| (Point3::new(x, y, z), block(x, y)))
+            .collect()
+    }
+
+    /// Splits every quad of a mesh into the unit faces it covers, as the position of the block
+    /// each unit face belongs to, the face, and the block there. Also checks that each unit face
+    /// is textured with its block's layer.
+    fn unit_faces(
+        neighborhood: &ChunkNeighborhood,
+        registry: &BlockRegistry,
+        vertices: &[Vertex],
+    ) -> HashSet<(Point3<i32>, usize, BlockId)> {
+        let mut faces = HashSet::new();
+        for quad in vertices.chunks(4) {
+            let words: Vec<[u32; 2]> = quad.iter().map(|&v| bytemuck::cast(v)).collect();
+            let position = |w: [u32; 2]| Point3::new(w[0] & 31, (w[0] >> 5) & 31, (w[0] >> 10) & 31).cast::<i32>().unwrap();
+            let face = CuboidFaces::ALL[((words[0][0] >> 15) & 7) as usize];
+            let layer = words[0][1] & 0xff;
+
+            let min = words.iter().map(|&w| position(w)).fold(Point3::new(i32::MAX, i32::MAX, i32::MAX), |a, b| {
+                Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+            });
+            let max = words.iter().map(|&w| position(w)).fold(Point3::new(i32::MIN, i32::MIN, i32::MIN), |a, b| {
+                Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+            });
+
+            // The quad is flat along its normal, and spans at least one block along the others.
+            let normal = face.normal();
+            let size = (max - min) + normal.map(|n| n.abs());
+            for x in 0..size.x {
+                for y in 0..size.y {
+                    for z in 0..size.z {
+                        // A face on the positive side of a block lies on the block's far plane.
+                        let pos = min + Vector3::new(x, y, z) - normal.map(|n| n.max(0));
+                        let block = neighborhood.get(pos).unwrap();
+                        assert_eq!(registry.get(block).layer(face), layer, "wrong texture at {:?}", pos);
+                        assert!(faces.insert((pos, face.index(), block)), "face at {:?} covered twice", pos);
+                    }
                 }
             }
-            for i in 0..z_max_delta {
-                m.add_cuboid_faces(Point3::new(x_i as i32, y_i as i32, z - i), faces);
+        }
+        faces
+    }
+
+    /// Mesh the neighborhood both ways, and check that the greedy mesher covers exactly the same
+    /// faces as the naive one, with fewer quads and triangles.
+    fn assert_greedy_matches_naive(blocks: &[(Point3<i32>, BlockId)]) {
+        let registry = BlockRegistry::for_tests();
+        let neighborhood = neighborhood(blocks);
+        let (naive_vertices, naive_indices) = mesh_chunk(&neighborhood, &registry, Mesher::Naive).report().unwrap();
+        let (greedy_vertices, greedy_indices) = mesh_chunk(&neighborhood, &registry, Mesher::Greedy).report().unwrap();
+
+        assert!(greedy_vertices.len() / 4 < naive_vertices.len() / 4);
+        assert!(greedy_indices.len() / 3 < naive_indices.len() / 3);
+        assert_eq!(naive_indices.len() / 6, naive_vertices.len() / 4);
+        assert_eq!(greedy_indices.len() / 6, greedy_vertices.len() / 4);
+        assert_eq!(
+            unit_faces(&neighborhood, &registry, &greedy_vertices),
+            unit_faces(&neighborhood, &registry, &naive_vertices),
+        );
+    }
+
+    #[test]
+    fn greedy_merges_a_flat_plain() {
+        let blocks = plain(4, |_, _| GRASS);
+        assert_greedy_matches_naive(&blocks);
+
+        // The top and bottom each merge into a single quad, as does each side of the plain.
+        let registry = BlockRegistry::for_tests();
+        let (vertices, _) = mesh_chunk(&neighborhood(&blocks), &registry, Mesher::Greedy).report().unwrap();
+        assert_eq!(vertices.len() / 4, 6);
+    }
+
+    #[test]
+    fn greedy_matches_naive_on_a_step() {
+        let mut blocks = plain(4, |_, _| DIRT);
+        blocks.extend(plain(5, |_, _| GRASS).into_iter().filter(|(pos, _)| pos.x >= 8));
+        assert_greedy_matches_naive(&blocks);
+    }
+
+    #[test]
+    fn greedy_matches_naive_with_mixed_blocks() {
+        let blocks = plain(4, |x, y| match (x / 4 + y / 4) % 3 {
+            0 => GRASS,
+            1 => DIRT,
+            _ => SNOW,
+        });
+        assert_greedy_matches_naive(&blocks);
+    }
+
+    /// Returns the ambient occlusion of the top of a dirt block at `pos`, with snow at each of
+    /// `around`.
+    fn top_ao(pos: Point3<i32>, around: &[Point3<i32>]) -> [u8; 4] {
+        let mut blocks = vec![(pos, DIRT)];
+        blocks.extend(around.iter().map(|&p| (p, SNOW)));
+        face_ao(&neighborhood(&blocks), &BlockRegistry::for_tests(), pos, CuboidFaces::TOP)
+    }
+
+    // The corners of the top face run +x+y, -x+y, -x-y, then +x-y.
+
+    #[test]
+    fn an_open_face_is_unoccluded() {
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[]), mesh::NO_OCCLUSION);
+        // Blocks beside the face, but not in front of it, don't occlude it.
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[Point3::new(6, 5, 5), Point3::new(5, 4, 5)]), mesh::NO_OCCLUSION);
+    }
+
+    #[test]
+    fn a_side_block_occludes_the_two_corners_it_touches() {
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[Point3::new(6, 5, 6)]), [2, 3, 3, 2]);
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[Point3::new(5, 4, 6)]), [3, 3, 2, 2]);
+    }
+
+    #[test]
+    fn a_corner_block_occludes_only_its_corner() {
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[Point3::new(6, 6, 6)]), [2, 3, 3, 3]);
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &[Point3::new(4, 4, 6)]), [3, 3, 2, 3]);
+    }
+
+    #[test]
+    fn two_side_blocks_fully_occlude_the_corner_between_them() {
+        let sides = [Point3::new(6, 5, 6), Point3::new(5, 6, 6)];
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &sides), [0, 2, 3, 2]);
+        // The corner block can't make it any darker.
+        let all = [sides[0], sides[1], Point3::new(6, 6, 6)];
+        assert_eq!(top_ao(Point3::new(5, 5, 5), &all), [0, 2, 3, 2]);
+    }
+
+    #[test]
+    fn blocks_in_adjacent_chunks_occlude_border_faces() {
+        let dim = CHUNK_DIM as i32;
+        assert_eq!(top_ao(Point3::new(dim - 1, 5, 5), &[Point3::new(dim, 5, 6)]), [2, 3, 3, 2]);
+        assert_eq!(top_ao(Point3::new(0, 0, dim - 1), &[Point3::new(-1, -1, dim)]), [3, 3, 2, 3]);
+    }
+
+    #[test]
+    fn the_load_box_holds_every_neighbor_of_every_visible_chunk() {
+        let center = Point3::new(3, -2, 1);
+        for view_radius in 0..8 {
+            for view_radius_z in 0..4 {
+                let (load_radius, load_radius_z) = load_radius(view_radius, view_radius_z);
+                let loaded: HashSet<_> = chunks_within_box(center, load_radius, load_radius_z).collect();
+                for pos in chunks_within(center, view_radius, view_radius_z) {
+                    for neighbor in neighborhood_of(pos) {
+                        assert!(
+                            loaded.contains(&neighbor) && within_box(center, neighbor, load_radius, load_radius_z),
+                            "neighbor {:?} of visible chunk {:?} isn't loaded at radius {}, {}",
+                            neighbor, pos, view_radius, view_radius_z,
+                        );
+                    }
+                }
             }
         }
     }
 
-    m
+    /// Returns the chunks remeshed by an edit at `pos`, sorted.
+    fn remeshed_by(pos: Point3<i32>) -> Vec<Point3<i32>> {
+        let mut chunks = chunks_touching_block(pos);
+        chunks.sort_by_key(|p| (p.x, p.y, p.z));
+        chunks
+    }
+
+    #[test]
+    fn editing_inside_a_chunk_remeshes_only_that_chunk() {
+        assert_eq!(remeshed_by(Point3::new(5, 6, 7)), vec![Point3::new(0, 0, 0)]);
+        assert_eq!(remeshed_by(Point3::new(-5, 20, 7)), vec![Point3::new(-1, 1, 0)]);
+    }
+
+    #[test]
+    fn editing_on_a_chunk_face_remeshes_the_chunk_across_it() {
+        let dim = CHUNK_DIM as i32;
+        assert_eq!(remeshed_by(Point3::new(dim - 1, 6, 7)), vec![Point3::new(0, 0, 0), Point3::new(1, 0, 0)]);
+        assert_eq!(remeshed_by(Point3::new(5, 0, 7)), vec![Point3::new(0, -1, 0), Point3::new(0, 0, 0)]);
+        assert_eq!(remeshed_by(Point3::new(5, 6, -dim)), vec![Point3::new(0, 0, -2), Point3::new(0, 0, -1)]);
+    }
+
+    #[test]
+    fn editing_on_a_chunk_corner_remeshes_every_chunk_touching_it() {
+        let dim = CHUNK_DIM as i32;
+        assert_eq!(remeshed_by(Point3::new(0, dim - 1, 7)), vec![
+            Point3::new(-1, 0, 0),
+            Point3::new(-1, 1, 0),
+            Point3::new(0, 0, 0),
+            Point3::new(0, 1, 0),
+        ]);
+        assert_eq!(remeshed_by(Point3::new(0, 0, 0)).len(), 8);
+    }
 }